@@ -167,6 +167,753 @@ CREATE TABLE reports (
     creation_date       TIMESTAMP       NOT NULL DEFAULT CURRENT_DATE
 );
 
+ALTER TABLE orders ADD COLUMN tracking_token VARCHAR UNIQUE;
+
+CREATE TABLE tariffs (
+    id                  BIGSERIAL       PRIMARY KEY,
+    version             BIGINT          NOT NULL UNIQUE,
+    base_fee            BIGINT          NOT NULL,
+    per_km              BIGINT          NOT NULL,
+    car_multiplier      DOUBLE PRECISION NOT NULL DEFAULT 1,
+    bicycle_multiplier  DOUBLE PRECISION NOT NULL DEFAULT 1,
+    feet_multiplier     DOUBLE PRECISION NOT NULL DEFAULT 1,
+    big_order_surcharge BIGINT          NOT NULL DEFAULT 0,
+    time_multipliers    JSONB           NOT NULL DEFAULT '[]',
+    min_price           BIGINT          NOT NULL,
+    courier_percent     SMALLINT        NOT NULL,
+    admin_id            BIGINT          REFERENCES admins(id) ON DELETE SET NULL,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO tariffs (version, base_fee, per_km, big_order_surcharge, time_multipliers, min_price, courier_percent)
+    VALUES (1, 10000, 3000, 10000, '[{"from":"22:00:00","till":"06:00:00","multiplier":1.2}]', 15000, 100);
+
+ALTER TABLE orders ADD COLUMN delivery_price BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE orders ADD COLUMN tariff_version BIGINT REFERENCES tariffs(version);
+
+ALTER TABLE couriers ADD COLUMN tier SMALLINT NOT NULL DEFAULT 0;
+
+CREATE TYPE CommissionKind AS ENUM (
+    'Fixed',
+    'Percentage',
+    'Tiered'
+);
+
+CREATE TABLE commission_rules (
+    id                  BIGSERIAL       PRIMARY KEY,
+    kind                CommissionKind  NOT NULL,
+    courier_tier        SMALLINT,
+    amount              BIGINT          NOT NULL DEFAULT 0,
+    tiers               JSONB           NOT NULL DEFAULT '[]',
+    effective_from      TIMESTAMP       NOT NULL,
+    admin_id            BIGINT          REFERENCES admins(id) ON DELETE SET NULL,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE payment_surcharges (
+    id                  BIGSERIAL       PRIMARY KEY,
+    method              PayMethod       NOT NULL,
+    percent             BIGINT          NOT NULL,
+    effective_from      TIMESTAMP       NOT NULL,
+    admin_id            BIGINT          REFERENCES admins(id) ON DELETE SET NULL,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE order_payouts (
+    id                  BIGSERIAL       PRIMARY KEY,
+    order_id            BIGINT          NOT NULL UNIQUE REFERENCES orders(id) ON DELETE CASCADE,
+    base_share          BIGINT          NOT NULL,
+    surcharge_id        BIGINT          REFERENCES payment_surcharges(id),
+    surcharge           BIGINT          NOT NULL DEFAULT 0,
+    courier_id          BIGINT          REFERENCES couriers(id) ON DELETE SET NULL,
+    commission_rule_id  BIGINT          REFERENCES commission_rules(id),
+    commission          BIGINT,
+    courier_payout      BIGINT,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finalize_datetime   TIMESTAMP
+);
+
+-- the values that used to be hardcoded
+INSERT INTO commission_rules (kind, amount, effective_from) VALUES ('Fixed', 1500, '2021-01-01');
+INSERT INTO payment_surcharges (method, percent, effective_from) VALUES ('Card', 400, '2021-01-01');
+
+-- orders placed before payouts were recorded keep the share stored on them
+INSERT INTO order_payouts (order_id, base_share)
+    SELECT id, courier_share FROM orders
+    ON CONFLICT (order_id) DO NOTHING;
+
+CREATE TYPE LedgerAccount AS ENUM (
+    'CourierSalary',
+    'CourierCash',
+    'CourierTerminal',
+    'Company'
+);
+
+CREATE TYPE LedgerEntryKind AS ENUM (
+    'Earning',
+    'CashCollected',
+    'TerminalCollected',
+    'Payout',
+    'Adjustment',
+    'Penalty'
+);
+
+CREATE TABLE ledger_transactions (
+    id                  BIGSERIAL       PRIMARY KEY,
+    kind                LedgerEntryKind NOT NULL,
+    courier_id          BIGINT          NOT NULL REFERENCES couriers(id) ON DELETE CASCADE,
+    order_id            BIGINT          REFERENCES orders(id) ON DELETE SET NULL,
+    actor_role          VARCHAR,
+    actor_id            BIGINT,
+    comment             VARCHAR,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE ledger_entries (
+    id                  BIGSERIAL       PRIMARY KEY,
+    transaction_id      BIGINT          NOT NULL REFERENCES ledger_transactions(id) ON DELETE CASCADE,
+    account             LedgerAccount   NOT NULL,
+    courier_id          BIGINT          NOT NULL REFERENCES couriers(id) ON DELETE CASCADE,
+    amount              BIGINT          NOT NULL
+);
+
+CREATE INDEX ledger_entries_courier_idx ON ledger_entries(courier_id, account);
+
+create or replace function ledger_immutable() returns trigger
+language plpgsql
+as $$
+begin
+    RAISE EXCEPTION 'ledger is append-only, post an adjustment instead';
+end;$$;
+
+CREATE TRIGGER ledger_transactions_immutable BEFORE UPDATE OR DELETE ON ledger_transactions
+    FOR EACH ROW EXECUTE PROCEDURE ledger_immutable();
+CREATE TRIGGER ledger_entries_immutable BEFORE UPDATE OR DELETE ON ledger_entries
+    FOR EACH ROW EXECUTE PROCEDURE ledger_immutable();
+
+INSERT INTO ledger_transactions (kind, courier_id, comment)
+    SELECT 'Adjustment', id, 'opening balance' FROM couriers
+    WHERE cash <> 0 OR term <> 0 OR salary <> 0;
+INSERT INTO ledger_entries (transaction_id, account, courier_id, amount)
+    SELECT t.id, a.account, c.id, a.amount
+    FROM ledger_transactions t JOIN couriers c ON c.id = t.courier_id,
+    LATERAL (VALUES
+        ('CourierSalary'::LedgerAccount, c.salary),
+        ('CourierCash'::LedgerAccount, c.cash),
+        ('CourierTerminal'::LedgerAccount, c.term),
+        ('Company'::LedgerAccount, -(c.salary + c.cash + c.term))
+    ) a(account, amount)
+    WHERE t.comment = 'opening balance' AND a.amount <> 0;
+
+ALTER TABLE couriers DROP COLUMN cash;
+ALTER TABLE couriers DROP COLUMN term;
+ALTER TABLE couriers DROP COLUMN salary;
+
+CREATE OR REPLACE VIEW courier_balances AS
+    SELECT
+        courier_id,
+        SUM(amount) FILTER (WHERE account = 'CourierSalary')::BIGINT as salary,
+        SUM(amount) FILTER (WHERE account = 'CourierCash')::BIGINT as cash,
+        SUM(amount) FILTER (WHERE account = 'CourierTerminal')::BIGINT as term
+    FROM ledger_entries
+    WHERE account <> 'Company'
+    GROUP BY courier_id;
+
+ALTER TYPE LedgerEntryKind ADD VALUE 'Handover';
+
+CREATE TYPE HandoverStatus AS ENUM (
+    'Declared',
+    'Confirmed',
+    'Disputed',
+    'Resolved'
+);
+
+CREATE TABLE cash_handovers (
+    id                  BIGSERIAL       PRIMARY KEY,
+    courier_id          BIGINT          NOT NULL REFERENCES couriers(id) ON DELETE CASCADE,
+    session_id          BIGINT          REFERENCES sessions(id) ON DELETE SET NULL,
+    declared_amount     BIGINT          NOT NULL,
+    received_amount     BIGINT,
+    discrepancy         BIGINT,
+    status              HandoverStatus  NOT NULL DEFAULT 'Declared',
+    -- curator or admin who counted the cash
+    actor_role          VARCHAR,
+    actor_id            BIGINT,
+    comment             VARCHAR,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    confirm_datetime    TIMESTAMP
+);
+
+CREATE TABLE cash_limits (
+    id                  BIGSERIAL   PRIMARY KEY,
+    courier_tier        SMALLINT,
+    courier_id          BIGINT      REFERENCES couriers(id) ON DELETE CASCADE,
+    amount              BIGINT      NOT NULL,
+    -- none for the seeded default
+    admin_id            BIGINT      REFERENCES admins(id),
+    creation_datetime   TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- the most specific limit wins: courier, then tier, then global
+create or replace function courier_cash_limit(
+    arg_courier_id BIGINT
+) returns BIGINT
+language sql
+as $$
+    SELECT l.amount
+    FROM cash_limits l JOIN couriers c ON c.id = arg_courier_id
+    WHERE l.courier_id = c.id
+        OR (l.courier_id IS NULL AND l.courier_tier = c.tier)
+        OR (l.courier_id IS NULL AND l.courier_tier IS NULL)
+    ORDER BY (l.courier_id IS NOT NULL) DESC, (l.courier_tier IS NOT NULL) DESC, l.creation_datetime DESC
+    LIMIT 1;
+$$;
+
+CREATE OR REPLACE VIEW courier_cash_status AS
+    SELECT
+        c.id as courier_id,
+        c.name as courier_name,
+        c.surname as courier_surname,
+        c.phone as courier_phone,
+        COALESCE(b.cash, 0) as cash,
+        courier_cash_limit(c.id) as cash_limit,
+        COALESCE(b.cash, 0) >= COALESCE(courier_cash_limit(c.id), 9223372036854775807) as exceeded
+    FROM couriers c
+        LEFT JOIN courier_balances b on b.courier_id = c.id;
+
+INSERT INTO cash_limits (amount) VALUES (1000000);
+
+ALTER TABLE orders ADD COLUMN tip BIGINT NOT NULL DEFAULT 0 CHECK (tip >= 0);
+ALTER TYPE LedgerEntryKind ADD VALUE 'Tip';
+
+CREATE TYPE SizeClass AS ENUM (
+    'Small',
+    'Medium',
+    'Large',
+    'Oversized'
+);
+
+CREATE TABLE order_items (
+    id                  BIGSERIAL   PRIMARY KEY,
+    order_id            BIGINT      NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    name                VARCHAR     NOT NULL,
+    quantity            INT         NOT NULL CHECK (quantity > 0),
+    unit_price          BIGINT      NOT NULL CHECK (unit_price >= 0),
+    weight              INT,
+    size_class          SizeClass   NOT NULL,
+    is_fragile          BOOLEAN     NOT NULL DEFAULT false,
+    is_drink            BOOLEAN     NOT NULL DEFAULT false,
+    is_hot              BOOLEAN     NOT NULL DEFAULT false
+);
+
+CREATE INDEX order_items_order_idx ON order_items(order_id);
+
+CREATE TABLE catalog_items (
+    id                  BIGSERIAL   PRIMARY KEY,
+    restaurant_id       BIGINT      NOT NULL REFERENCES restaurants(id) ON DELETE CASCADE,
+    name                VARCHAR     NOT NULL,
+    price               BIGINT      NOT NULL CHECK (price >= 0),
+    weight              INT,
+    size_class          SizeClass   NOT NULL DEFAULT 'Small',
+    is_fragile          BOOLEAN     NOT NULL DEFAULT false,
+    is_drink            BOOLEAN     NOT NULL DEFAULT false,
+    is_hot              BOOLEAN     NOT NULL DEFAULT false,
+    is_available        BOOLEAN     NOT NULL DEFAULT true,
+    creation_datetime   TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (restaurant_id, name)
+);
+
+ALTER TABLE order_items ADD COLUMN catalog_item_id BIGINT REFERENCES catalog_items(id) ON DELETE SET NULL;
+
+create or replace function mask_phone(
+    phone VARCHAR
+) returns VARCHAR
+language sql immutable
+as $$
+    SELECT CASE WHEN length(phone) <= 4 THEN repeat('*', length(phone))
+        ELSE left(phone, 2) || repeat('*', length(phone) - 4) || right(phone, 2) END;
+$$;
+
+CREATE TABLE phone_access_log (
+    id                  BIGSERIAL   PRIMARY KEY,
+    order_id            BIGINT      NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    actor_role          VARCHAR     NOT NULL,
+    actor_id            BIGINT      NOT NULL,
+    reason              VARCHAR,
+    creation_datetime   TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TYPE OrderStatus ADD VALUE 'Returning';
+ALTER TYPE OrderStatus ADD VALUE 'Returned';
+
+CREATE TYPE ReturnReason AS ENUM (
+    'NoAnswer',
+    'Refused',
+    'WrongAddress',
+    'Other'
+);
+
+CREATE TABLE order_returns (
+    id                  BIGSERIAL       PRIMARY KEY,
+    order_id            BIGINT          NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    courier_id          BIGINT          NOT NULL REFERENCES couriers(id) ON DELETE CASCADE,
+    reason              ReturnReason    NOT NULL,
+    comment             VARCHAR,
+    lat                 DOUBLE PRECISION,
+    lng                 DOUBLE PRECISION,
+    report_datetime     TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    confirm_datetime    TIMESTAMP,
+    restaurant_comment  VARCHAR
+);
+
+CREATE TABLE return_rules (
+    id                  BIGSERIAL   PRIMARY KEY,
+    share_percent       SMALLINT    NOT NULL,
+    base_fee            BIGINT      NOT NULL DEFAULT 0,
+    pay_cash_orders     BOOLEAN     NOT NULL DEFAULT true,
+    effective_from      TIMESTAMP   NOT NULL,
+    admin_id            BIGINT      REFERENCES admins(id) ON DELETE SET NULL,
+    creation_datetime   TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO return_rules (share_percent, base_fee, pay_cash_orders, effective_from)
+    VALUES (50, 0, true, '2021-01-01');
+
+CREATE TYPE OrderEventKind AS ENUM (
+    'StatusChanged',
+    'CourierArrived'
+);
+
+CREATE TABLE order_events (
+    id                  BIGSERIAL       PRIMARY KEY,
+    order_id            BIGINT          NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    kind                OrderEventKind  NOT NULL,
+    status              OrderStatus,
+    courier_id          BIGINT          REFERENCES couriers(id) ON DELETE SET NULL,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX order_events_order_idx ON order_events(order_id);
+
+create or replace function log_order_status() returns trigger
+language plpgsql
+as $$
+begin
+    IF TG_OP = 'INSERT' OR NEW.status IS DISTINCT FROM OLD.status THEN
+        INSERT INTO order_events (order_id, kind, status, courier_id)
+            VALUES (NEW.id, 'StatusChanged', NEW.status,
+                (SELECT courier_id FROM sessions WHERE id = NEW.session_id));
+    END IF;
+    return NEW;
+end;$$;
+
+CREATE TRIGGER orders_status_events AFTER INSERT OR UPDATE OF status ON orders
+    FOR EACH ROW EXECUTE PROCEDURE log_order_status();
+
+CREATE TABLE rating_criteria (
+    id                  BIGSERIAL   PRIMARY KEY,
+    name                VARCHAR     NOT NULL,
+    is_active           BOOLEAN     NOT NULL DEFAULT true,
+    creation_datetime   TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO rating_criteria (name) VALUES
+    ('Время ожидания'),
+    ('Упаковка'),
+    ('Персонал');
+
+CREATE TABLE restaurant_ratings (
+    id                  BIGSERIAL   PRIMARY KEY,
+    order_id            BIGINT      NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    courier_id          BIGINT      NOT NULL REFERENCES couriers(id) ON DELETE CASCADE,
+    restaurant_id       BIGINT      NOT NULL REFERENCES restaurants(id) ON DELETE CASCADE,
+    criterion_id        BIGINT      NOT NULL REFERENCES rating_criteria(id),
+    score               SMALLINT    NOT NULL CHECK (score BETWEEN 1 AND 5),
+    comment             VARCHAR,
+    creation_datetime   TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (order_id, criterion_id)
+);
+
+-- seconds between the courier's arrival and the pickup
+CREATE OR REPLACE VIEW pickup_waits AS
+    SELECT
+        o.id as order_id,
+        o.restaurant_id,
+        EXTRACT(EPOCH FROM (p.creation_datetime - a.creation_datetime))::DOUBLE PRECISION as wait
+    FROM orders o
+        JOIN LATERAL (SELECT MIN(creation_datetime) as creation_datetime FROM order_events
+            WHERE order_id = o.id AND kind = 'CourierArrived') a ON a.creation_datetime IS NOT NULL
+        JOIN LATERAL (SELECT MIN(creation_datetime) as creation_datetime FROM order_events
+            WHERE order_id = o.id AND kind = 'StatusChanged' AND status = 'Delivering') p
+            ON p.creation_datetime IS NOT NULL
+    WHERE p.creation_datetime >= a.creation_datetime;
+
+-- a minute of waiting costs 0.2 points, five minutes a whole star
+CREATE OR REPLACE VIEW restaurant_scores AS
+    SELECT
+        r.id as restaurant_id,
+        r.name as restaurant_name,
+        COALESCE(rt.ratings_count, 0) as ratings_count,
+        rt.rating,
+        w.avg_wait,
+        ws.wait_score,
+        (CASE
+            WHEN rt.rating IS NULL THEN ws.wait_score
+            WHEN ws.wait_score IS NULL THEN rt.rating
+            ELSE (rt.rating + ws.wait_score) / 2
+        END)::DOUBLE PRECISION as score
+    FROM restaurants r
+        LEFT JOIN (SELECT restaurant_id, COUNT(DISTINCT order_id) as ratings_count,
+                AVG(score)::DOUBLE PRECISION as rating
+            FROM restaurant_ratings GROUP BY restaurant_id) rt ON rt.restaurant_id = r.id
+        LEFT JOIN (SELECT restaurant_id, AVG(wait) as avg_wait
+            FROM pickup_waits GROUP BY restaurant_id) w ON w.restaurant_id = r.id,
+        LATERAL (SELECT (CASE WHEN w.avg_wait IS NULL THEN NULL
+            ELSE GREATEST(1, 5 - w.avg_wait / 300) END)::DOUBLE PRECISION as wait_score) ws
+    WHERE NOT r.is_deleted;
+
+ALTER TABLE courier_rating ADD COLUMN comment VARCHAR;
+ALTER TABLE courier_rating ADD COLUMN restaurant_id BIGINT REFERENCES restaurants(id) ON DELETE SET NULL;
+ALTER TABLE courier_rating ADD COLUMN creation_datetime TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+UPDATE courier_rating cr SET restaurant_id = o.restaurant_id FROM orders o WHERE o.id = cr.order_id;
+UPDATE courier_rating cr SET creation_datetime = COALESCE(o.finalize_datetime, o.creation_datetime)
+    FROM orders o WHERE o.id = cr.order_id;
+UPDATE courier_rating SET look = LEAST(GREATEST(look, 1), 5), politeness = LEAST(GREATEST(politeness, 1), 5);
+ALTER TABLE courier_rating ADD CONSTRAINT courier_rating_range
+    CHECK (look BETWEEN 1 AND 5 AND politeness BETWEEN 1 AND 5);
+
+-- the courier rating is computed from courier_rating, the running sums are gone;
+-- the views reading them are recreated below
+DROP TRIGGER IF EXISTS trigger_proc_rating ON courier_rating;
+DROP FUNCTION IF EXISTS proc_rating();
+ALTER TABLE couriers DROP COLUMN current_rate_amount CASCADE;
+ALTER TABLE couriers DROP COLUMN current_rate_count CASCADE;
+
+CREATE TYPE DisputeCategory AS ENUM (
+    'Late',
+    'Damaged',
+    'Missing',
+    'WrongOrder',
+    'Payment',
+    'Behaviour',
+    'Other'
+);
+
+CREATE TYPE DisputeStatus AS ENUM (
+    'Open',
+    'InReview',
+    'Resolved',
+    'Rejected'
+);
+
+CREATE TABLE disputes (
+    id                  BIGSERIAL       PRIMARY KEY,
+    order_id            BIGINT          NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    opened_by_role      VARCHAR         NOT NULL,
+    opened_by_id        BIGINT          NOT NULL,
+    category            DisputeCategory NOT NULL,
+    description         VARCHAR         NOT NULL,
+    attachments         TEXT[]          NOT NULL DEFAULT '{}',
+    status              DisputeStatus   NOT NULL DEFAULT 'Open',
+    reviewer_role       VARCHAR,
+    reviewer_id         BIGINT,
+    previous_status     OrderStatus,
+    new_status          OrderStatus,
+    adjustment_id       BIGINT          REFERENCES ledger_transactions(id),
+    resolution          VARCHAR,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    resolve_datetime    TIMESTAMP
+);
+
+CREATE INDEX disputes_order_idx ON disputes(order_id);
+
+ALTER TYPE OrderEventKind ADD VALUE 'DisputeOpened';
+ALTER TYPE OrderEventKind ADD VALUE 'DisputeInReview';
+ALTER TYPE OrderEventKind ADD VALUE 'FaultChanged';
+ALTER TYPE OrderEventKind ADD VALUE 'DisputeAdjusted';
+ALTER TYPE OrderEventKind ADD VALUE 'DisputeResolved';
+ALTER TYPE OrderEventKind ADD VALUE 'DisputeRejected';
+ALTER TABLE order_events ADD COLUMN dispute_id BIGINT REFERENCES disputes(id) ON DELETE CASCADE;
+
+CREATE TABLE chat_templates (
+    id                  BIGSERIAL       PRIMARY KEY,
+    role                VARCHAR,
+    body                VARCHAR         NOT NULL,
+    is_active           BOOLEAN         NOT NULL DEFAULT true,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO chat_templates (role, body) VALUES
+    ('courier', 'Я на месте'),
+    ('courier', 'Клиент не отвечает'),
+    ('courier', 'Задерживаюсь на 10 минут'),
+    ('restaurant', 'Заказ задерживается на 10 минут'),
+    ('restaurant', 'Клиент изменил подъезд'),
+    ('restaurant', 'Заказ готов');
+
+CREATE TABLE order_messages (
+    id                  BIGSERIAL       PRIMARY KEY,
+    order_id            BIGINT          NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    author_role         VARCHAR         NOT NULL,
+    author_id           BIGINT          NOT NULL,
+    body                VARCHAR         NOT NULL,
+    template_id         BIGINT          REFERENCES chat_templates(id) ON DELETE SET NULL,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX order_messages_order_idx ON order_messages(order_id, id);
+
+CREATE TABLE order_message_reads (
+    order_id            BIGINT          NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    reader_role         VARCHAR         NOT NULL,
+    reader_id           BIGINT          NOT NULL,
+    last_read_id        BIGINT          NOT NULL,
+    read_datetime       TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (order_id, reader_role, reader_id)
+);
+
+CREATE TABLE delivery_zones (
+    id                  BIGSERIAL           PRIMARY KEY,
+    restaurant_id       BIGINT              REFERENCES restaurants(id) ON DELETE CASCADE,
+    city                VARCHAR,
+    name                VARCHAR             NOT NULL,
+    geometry            JSONB               NOT NULL,
+    tariff_multiplier   DOUBLE PRECISION    NOT NULL DEFAULT 1 CHECK (tariff_multiplier > 0),
+    max_distance        INTEGER             CHECK (max_distance > 0),
+    is_active           BOOLEAN             NOT NULL DEFAULT true,
+    admin_id            BIGINT              REFERENCES admins(id) ON DELETE SET NULL,
+    creation_datetime   TIMESTAMP           NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX delivery_zones_restaurant_idx ON delivery_zones(restaurant_id);
+
+-- shared zones apply to the restaurants of their city
+ALTER TABLE restaurants ADD COLUMN city VARCHAR;
+
+ALTER TABLE orders ADD COLUMN zone_id BIGINT REFERENCES delivery_zones(id) ON DELETE SET NULL;
+
+ALTER TABLE orders ADD COLUMN parent_id BIGINT REFERENCES orders(id) ON DELETE CASCADE;
+ALTER TABLE orders ADD COLUMN is_split BOOLEAN NOT NULL DEFAULT false;
+CREATE INDEX orders_parent_idx ON orders(parent_id);
+
+-- the parent mirrors its parcels for tracking; reports skip it (is_split)
+-- because the parcels already carry the money
+create or replace function sync_parent_order() returns trigger
+language plpgsql
+as $$
+declare
+    pending BIGINT;
+begin
+    SELECT COUNT(*) FROM orders WHERE parent_id = NEW.parent_id AND finalize_datetime IS NULL
+        INTO pending;
+    IF pending > 0 THEN
+        UPDATE orders SET status = (SELECT MIN(status) FROM orders
+                WHERE parent_id = NEW.parent_id AND finalize_datetime IS NULL)
+            WHERE id = NEW.parent_id;
+    ELSE
+        UPDATE orders SET
+            status = COALESCE((SELECT status FROM orders
+                WHERE parent_id = NEW.parent_id AND status != 'Success' ORDER BY id LIMIT 1), 'Success'),
+            finalize_datetime = CURRENT_TIMESTAMP
+            WHERE id = NEW.parent_id AND finalize_datetime IS NULL;
+    END IF;
+    return NEW;
+end;$$;
+
+CREATE TRIGGER orders_sync_parent AFTER UPDATE OF status, finalize_datetime ON orders
+    FOR EACH ROW WHEN (NEW.parent_id IS NOT NULL) EXECUTE PROCEDURE sync_parent_order();
+
+CREATE TABLE shift_slots (
+    id                  BIGSERIAL       PRIMARY KEY,
+    zone_id             BIGINT          REFERENCES delivery_zones(id) ON DELETE SET NULL,
+    start_at            TIMESTAMP       NOT NULL,
+    end_at              TIMESTAMP       NOT NULL,
+    car_quota           INTEGER         NOT NULL DEFAULT 0 CHECK (car_quota >= 0),
+    bicycle_quota       INTEGER         NOT NULL DEFAULT 0 CHECK (bicycle_quota >= 0),
+    feet_quota          INTEGER         NOT NULL DEFAULT 0 CHECK (feet_quota >= 0),
+    is_cancelled        BOOLEAN         NOT NULL DEFAULT false,
+    curator_id          BIGINT,
+    creation_datetime   TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK (start_at < end_at)
+);
+
+CREATE INDEX shift_slots_start_idx ON shift_slots(start_at);
+
+CREATE TYPE ShiftBookingStatus AS ENUM (
+    'Booked',
+    'Cancelled',
+    'Attended',
+    'NoShow'
+);
+
+CREATE TABLE shift_bookings (
+    id                  BIGSERIAL           PRIMARY KEY,
+    slot_id             BIGINT              NOT NULL REFERENCES shift_slots(id) ON DELETE CASCADE,
+    courier_id          BIGINT              NOT NULL REFERENCES couriers(id) ON DELETE CASCADE,
+    transport           TransportType       NOT NULL,
+    status              ShiftBookingStatus  NOT NULL DEFAULT 'Booked',
+    session_id          BIGINT              REFERENCES sessions(id) ON DELETE SET NULL,
+    creation_datetime   TIMESTAMP           NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX shift_bookings_active_idx ON shift_bookings(slot_id, courier_id)
+    WHERE status != 'Cancelled';
+
+ALTER TABLE sessions ADD COLUMN booking_id BIGINT REFERENCES shift_bookings(id) ON DELETE SET NULL;
+ALTER TABLE sessions ADD COLUMN override_by BIGINT;
+
+CREATE OR REPLACE VIEW shift_slot_load AS
+    SELECT
+        s.id as slot_id,
+        s.zone_id,
+        z.name as zone_name,
+        s.start_at,
+        s.end_at,
+        (s.car_quota + s.bicycle_quota + s.feet_quota)::BIGINT as headcount,
+        s.car_quota::BIGINT as car_quota,
+        s.bicycle_quota::BIGINT as bicycle_quota,
+        s.feet_quota::BIGINT as feet_quota,
+        COUNT(b.id) FILTER (WHERE b.transport = 'Car') as car_booked,
+        COUNT(b.id) FILTER (WHERE b.transport = 'Bicycle') as bicycle_booked,
+        COUNT(b.id) FILTER (WHERE b.transport = 'Feet') as feet_booked,
+        COUNT(b.id) FILTER (WHERE b.status = 'Attended') as attended,
+        COUNT(b.id) FILTER (WHERE b.status = 'NoShow') as no_shows
+    FROM shift_slots s
+        LEFT JOIN delivery_zones z ON z.id = s.zone_id
+        LEFT JOIN shift_bookings b ON b.slot_id = s.id AND b.status != 'Cancelled'
+    WHERE NOT s.is_cancelled
+    GROUP BY s.id, z.name;
+
+create or replace function process_no_shows() returns void
+language plpgsql
+as $$ begin
+    UPDATE shift_bookings SET status = 'NoShow'
+        WHERE status = 'Booked' AND slot_id IN (SELECT id FROM shift_slots
+            WHERE start_at + interval '15 minutes' < CURRENT_TIMESTAMP);
+end;$$;
+
+ALTER TABLE sessions ADD COLUMN start_at TIMESTAMP;
+ALTER TABLE sessions ADD COLUMN end_at TIMESTAMP;
+ALTER TABLE sessions ADD COLUMN ended_at TIMESTAMP;
+ALTER TABLE sessions ADD COLUMN end_warned BOOLEAN NOT NULL DEFAULT false;
+UPDATE sessions SET
+    start_at = session_day + start_time,
+    end_at = session_day + end_time
+        + CASE WHEN end_time <= start_time THEN interval '1 day' ELSE interval '0' END,
+    ended_at = session_day + end_real_time
+        + CASE WHEN end_real_time < start_time THEN interval '1 day' ELSE interval '0' END
+    WHERE start_at IS NULL;
+ALTER TABLE sessions ALTER COLUMN start_at SET NOT NULL;
+ALTER TABLE sessions ALTER COLUMN end_at SET NOT NULL;
+ALTER TABLE sessions DROP COLUMN start_time CASCADE;
+ALTER TABLE sessions DROP COLUMN end_time CASCADE;
+ALTER TABLE sessions DROP COLUMN session_day CASCADE;
+ALTER TABLE sessions DROP COLUMN end_real_time CASCADE;
+ALTER TABLE sessions ADD CONSTRAINT sessions_period CHECK (start_at < end_at);
+CREATE UNIQUE INDEX sessions_one_open ON sessions (courier_id) WHERE ended_at IS NULL;
+
+ALTER TABLE notifications ADD COLUMN courier_id BIGINT REFERENCES couriers(id) ON DELETE CASCADE;
+
+CREATE TABLE break_rules (
+    id                  BIGSERIAL   PRIMARY KEY,
+    max_breaks          SMALLINT    NOT NULL CHECK (max_breaks >= 0),
+    max_minutes         INTEGER     NOT NULL CHECK (max_minutes > 0),
+    effective_from      TIMESTAMP   NOT NULL,
+    admin_id            BIGINT      REFERENCES admins(id) ON DELETE SET NULL,
+    creation_datetime   TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO break_rules (max_breaks, max_minutes, effective_from)
+    VALUES (2, 30, '2021-01-01');
+
+CREATE TABLE session_breaks (
+    id                  BIGSERIAL   PRIMARY KEY,
+    session_id          BIGINT      NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    courier_id          BIGINT      NOT NULL REFERENCES couriers(id) ON DELETE CASCADE,
+    started_at          TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at            TIMESTAMP,
+    auto_resumed        BOOLEAN     NOT NULL DEFAULT false
+);
+CREATE UNIQUE INDEX session_breaks_one_open ON session_breaks (courier_id) WHERE ended_at IS NULL;
+
+CREATE OR REPLACE VIEW session_break_report AS
+    SELECT
+        s.id as session_id,
+        c.id as courier_id,
+        c.name as courier_name,
+        c.surname as courier_surname,
+        s.start_at::DATE as session_day,
+        COUNT(b.id) as breaks,
+        COUNT(b.id) FILTER (WHERE b.auto_resumed) as auto_resumed,
+        COALESCE(EXTRACT(EPOCH FROM SUM(COALESCE(b.ended_at, CURRENT_TIMESTAMP::TIMESTAMP) - b.started_at))::BIGINT / 60, 0)
+            as break_minutes
+    FROM sessions s
+        JOIN couriers c on c.id = s.courier_id
+        LEFT JOIN session_breaks b on b.session_id = s.id
+    GROUP BY s.id, c.id;
+
+CREATE TYPE AvailabilityStatus AS ENUM (
+    'Offline',
+    'OnlineIdle',
+    'Offered',
+    'Busy',
+    'Paused',
+    'StaleLocation'
+);
+
+-- one row per stretch of time a courier spent in a status, the open one is the current status
+CREATE TABLE courier_availability_log (
+    id                  BIGSERIAL           PRIMARY KEY,
+    courier_id          BIGINT              NOT NULL REFERENCES couriers(id) ON DELETE CASCADE,
+    status              AvailabilityStatus  NOT NULL,
+    session_id          BIGINT              REFERENCES sessions(id) ON DELETE SET NULL,
+    started_at          TIMESTAMP           NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at            TIMESTAMP
+);
+CREATE UNIQUE INDEX courier_availability_log_open ON courier_availability_log (courier_id) WHERE ended_at IS NULL;
+CREATE INDEX courier_availability_log_period ON courier_availability_log (started_at, ended_at);
+
+-- seconds spent online and busy by every courier in every hour of the period
+create or replace function courier_utilisation(
+    arg_from TIMESTAMP,
+    arg_till TIMESTAMP
+) returns table (
+    courier_id BIGINT,
+    hour TIMESTAMP,
+    online_seconds BIGINT,
+    busy_seconds BIGINT,
+    paused_seconds BIGINT
+)
+language sql
+as $$
+    SELECT
+        l.courier_id,
+        h.hour,
+        SUM(EXTRACT(EPOCH FROM LEAST(COALESCE(l.ended_at, CURRENT_TIMESTAMP::TIMESTAMP), h.hour + interval '1 hour')
+            - GREATEST(l.started_at, h.hour)))::BIGINT,
+        COALESCE(SUM(EXTRACT(EPOCH FROM LEAST(COALESCE(l.ended_at, CURRENT_TIMESTAMP::TIMESTAMP), h.hour + interval '1 hour')
+            - GREATEST(l.started_at, h.hour))) FILTER (WHERE l.status IN ('Busy', 'Offered')), 0)::BIGINT,
+        COALESCE(SUM(EXTRACT(EPOCH FROM LEAST(COALESCE(l.ended_at, CURRENT_TIMESTAMP::TIMESTAMP), h.hour + interval '1 hour')
+            - GREATEST(l.started_at, h.hour))) FILTER (WHERE l.status = 'Paused'), 0)::BIGINT
+    FROM generate_series(date_trunc('hour', arg_from), arg_till - interval '1 microsecond', interval '1 hour') h(hour)
+        JOIN courier_availability_log l ON l.status <> 'Offline'
+            AND l.started_at < h.hour + interval '1 hour'
+            AND COALESCE(l.ended_at, CURRENT_TIMESTAMP::TIMESTAMP) > h.hour
+    GROUP BY l.courier_id, h.hour
+    ORDER BY l.courier_id, h.hour;
+$$;
+
+CREATE TABLE location_points (
+    id                  BIGSERIAL           PRIMARY KEY,
+    courier_id          BIGINT              NOT NULL REFERENCES couriers(id) ON DELETE CASCADE,
+    session_id          BIGINT              REFERENCES sessions(id) ON DELETE SET NULL,
+    lat                 DOUBLE PRECISION    NOT NULL,
+    lng                 DOUBLE PRECISION    NOT NULL,
+    recorded_at         TIMESTAMP           NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX location_points_courier ON location_points (courier_id, recorded_at);
+CREATE INDEX location_points_session ON location_points (session_id, recorded_at);
+
 SELECT * FROM get_notification(4);
 
 create or replace function get_notification(
@@ -183,7 +930,9 @@ declare
 begin
     SELECT couriers.creation_datetime FROM couriers WHERE id=arg_courier_id INTO courier;
     SELECT array_agg(n.id) FROM notifications n
-    WHERE n.creation_datetime > courier AND n.id NOT IN (
+    WHERE n.creation_datetime > courier
+        AND (n.courier_id IS NULL OR n.courier_id = arg_courier_id)
+        AND n.id NOT IN (
         SELECT notifications_to_couriers.notific_id FROM notifications_to_couriers WHERE courier_id=arg_courier_id
         )
     INTO notif;
@@ -226,24 +975,6 @@ CREATE TRIGGER trigger_roll_to_finding_d
   FOR EACH ROW
   EXECUTE PROCEDURE roll_to_finding();
 
-CREATE OR REPLACE FUNCTION proc_rating()
-  RETURNS TRIGGER
-  LANGUAGE PLPGSQL
-  AS
-$$BEGIN
-	UPDATE couriers SET current_rate_amount =
-	    (current_rate_amount + NEW.look + NEW.politeness),
-	    current_rate_count = (current_rate_count + 2) WHERE id = NEW.courier_id;
-	RETURN NEW;
-END;$$;
-
-
-CREATE TRIGGER trigger_proc_rating
-  AFTER INSERT
-  ON courier_rating
-  FOR EACH ROW
-  EXECUTE PROCEDURE proc_rating();
-
 create or replace function process_approvals() returns void
 language plpgsql
 as $$ begin
@@ -252,8 +983,6 @@ as $$ begin
         CURRENT_TIMESTAMP > couriers_approvals.datetime + (5 ||' minutes')::interval AND not couriers.is_in_order);
     DELETE FROM couriers_approvals WHERE courier_id IN (SELECT courier_id FROM couriers_approvals WHERE
         CURRENT_TIMESTAMP > couriers_approvals.datetime + (5 ||' minutes')::interval);
-    UPDATE sessions SET end_real_time=CURRENT_TIMESTAMP
-        WHERE end_time > CURRENT_TIMESTAMP AND end_real_time IS NULL AND session_day <= CURRENT_DATE;
 end;$$;
 
 select * FROM find_suitable_orders(0,0,3);
@@ -278,23 +1007,30 @@ create or replace function find_suitable_orders(
 declare
     courier couriers;
     courier_session sessions;
+    cash_exceeded BOOLEAN := COALESCE((SELECT exceeded FROM courier_cash_status
+        WHERE courier_id=arg_courier_id), false);
     ret bigint;
 begin
     SELECT * FROM couriers WHERE id=arg_courier_id INTO courier;
     SELECT * FROM sessions WHERE courier_id=arg_courier_id
-                                                          AND sessions.session_day = CURRENT_DATE
-                                                          AND sessions.end_time > CURRENT_TIME INTO courier_session;
+                                                          AND sessions.ended_at IS NULL
+                                                          AND sessions.start_at <= CURRENT_TIMESTAMP
+                                                          AND sessions.end_at > CURRENT_TIMESTAMP INTO courier_session;
+    IF EXISTS (SELECT 1 FROM session_breaks WHERE courier_id=arg_courier_id AND ended_at IS NULL) THEN
+        return;
+    END IF;
     SELECT
                 orders.id
         FROM orders
             JOIN restaurants r on orders.restaurant_id = r.id
-        WHERE status='CourierFinding' and
+        WHERE status='CourierFinding' and not orders.is_split and
               (CASE WHEN not courier_session.transport='Car' THEN orders.is_big_order=false
 		        ELSE TRUE
 	        END) and
             (CASE WHEN not courier_session.has_terminal=false THEN orders.method!='Card'
 		        ELSE TRUE
-	        END)
+	        END) and
+            (not cash_exceeded OR orders.method!='Cash')
         GROUP BY orders.id,r.location_lat,r.location_lng
         ORDER BY (2 * 3961 * ASIN(SQRT( POWER((SIN(RADIANS((r.location_lat - arg_lat) / 2))) , 2) + COS(RADIANS(arg_lat
         )) * COS(RADIANS(r.location_lat)) * POWER((SIN(RADIANS((r.location_lng - arg_lng) / 2))) , 2) )))
@@ -374,7 +1110,7 @@ as $$ begin
     SET
         status='ReadyForDelivery'
     WHERE
-        id=arg_order_id AND status='Cooking';
+        (id=arg_order_id OR parent_id=arg_order_id) AND status='Cooking';
     return;
 end;$$;
 
@@ -417,14 +1153,20 @@ language plpgsql
 as $$
     declare
         session_id bigint := (SELECT id FROM sessions
-            WHERE end_real_time IS NULL AND courier_id = arg_order_id);
+            WHERE ended_at IS NULL AND courier_id = arg_order_id);
     begin
     UPDATE
         sessions
     SET
-        end_real_time = CURRENT_TIME
+        ended_at = CURRENT_TIMESTAMP
     WHERE
         sessions.id = session_id;
+    UPDATE
+        session_breaks
+    SET
+        ended_at = CURRENT_TIMESTAMP
+    WHERE
+        session_breaks.session_id = end_session.session_id AND ended_at IS NULL;
     return;
 end;$$;
 
@@ -437,15 +1179,14 @@ create or replace function finalize_order(
 language plpgsql
 as $$
 declare
-    var_order orders;
-    var_courier couriers;
+    var_courier_id BIGINT;
 begin
 
     UPDATE
         orders
     SET
-        orders.finalize_comment = comment,
-        orders.finalize_datetime = CURRENT_TIMESTAMP,
+        finalize_comment = comment,
+        finalize_datetime = CURRENT_TIMESTAMP,
         status=(
             CASE WHEN is_success THEN 'Success' ELSE
                 CASE WHEN courier_fault THEN 'FailureByCourier'
@@ -455,28 +1196,11 @@ begin
             END)
     WHERE
         id=arg_order_id AND (status='Delivered' OR not is_success);
-    SELECT * FROM orders WHERE id=arg_order_id INTO var_order;
-
-    var_courier = (SELECT courier_id FROM orders o JOIN sessions s
-                ON o.session_id=s.courier_id JOIN couriers c ON c.id=s.courier_id
-                WHERE o.id=var_order.id);
-
-    IF var_order.status = 'Success' THEN
-        UPDATE couriers SET couriers.salary= couriers.salary + (var_order.courier_share - 1500) WHERE couriers.id=var_courier.id;
-        IF var_order.method='Cash' THEN
-            UPDATE couriers SET couriers.cash = couriers.cash + var_order.order_price WHERE couriers.id=var_courier.id;
-        END IF;
-        IF var_order.method='Card' THEN
-            UPDATE couriers SET couriers.term = couriers.term + var_order.order_price WHERE couriers.id=var_courier.id;
-        END IF;
-    END IF;
-    IF var_order.status = 'FailureByRestaurant' THEN
-        UPDATE couriers SET couriers.salary= couriers.salary + (var_order.courier_share - 1500) WHERE couriers.id=var_courier.id;
-    END IF;
-    UPDATE couriers SET is_in_order=false WHERE id IN (
-        SELECT courier_id
-        FROM orders JOIN sessions s on orders.session_id = s.id
-        WHERE s.courier_id=var_courier.id);
+
+    var_courier_id = (SELECT s.courier_id FROM orders o JOIN sessions s
+                ON o.session_id=s.id WHERE o.id=arg_order_id);
+
+    UPDATE couriers SET is_in_order=false WHERE id=var_courier_id;
     return;
 end;$$;
 
@@ -487,12 +1211,10 @@ CREATE OR REPLACE VIEW courier_for_admin AS
         c.surname as surname,
         c.patronymic as patronymic,
         s.transport,
-        c.current_rate_count,
-        c.current_rate_amount,
         c.phone,
         c.picture
     FROM couriers c
-        LEFT JOIN sessions s on c.id = s.courier_id and s.end_real_time is null
+        LEFT JOIN sessions s on c.id = s.courier_id and s.ended_at is null
         JOIN orders o on s.id = o.session_id;
 
 CREATE OR REPLACE VIEW courier_info AS
@@ -503,11 +1225,9 @@ CREATE OR REPLACE VIEW courier_info AS
         c.patronymic as courier_patronymic,
         c.picture as courier_picture,
         c.phone as courier_phone,
-        c.current_rate_count as courier_current_rate_count,
-        c.current_rate_amount as courier_current_rate_ammount,
-        c.term as courier_card_balance,
-        c.salary as courier_salary,
-        c.cash as courier_cash_balance,
+        COALESCE(b.term, 0) as courier_card_balance,
+        COALESCE(b.salary, 0) as courier_salary,
+        COALESCE(b.cash, 0) as courier_cash_balance,
         c.is_in_order as courier_is_in_order,
         c.is_warned as courier_is_warned,
         c.is_blocked as courier_is_blocked,
@@ -521,20 +1241,78 @@ CREATE OR REPLACE VIEW courier_info AS
         o.details as order_details,
         o.order_price,
         o.client_comment,
-        o.client_phone,
-        s.transport
+        CASE WHEN o.status = ANY('{CourierConfirmation,Cooking,ReadyForDelivery,Delivering}')
+            THEN o.client_phone ELSE mask_phone(o.client_phone) END as client_phone,
+        s.transport,
+        o.tip as order_tip
     FROM couriers c
-        LEFT JOIN sessions s on c.id = s.courier_id and s.end_real_time is null
+        LEFT JOIN courier_balances b on b.courier_id = c.id
+        LEFT JOIN sessions s on c.id = s.courier_id and s.ended_at is null
         JOIN orders o on s.id = o.session_id;
 
 select * from courier_for_admin;
 select * from courier_info WHERE courier_id=$1;
 
+CREATE OR REPLACE VIEW shift_cash_report AS
+    SELECT
+        s.id as session_id,
+        c.id as courier_id,
+        c.name as courier_name,
+        c.surname as courier_surname,
+        s.start_at::DATE as session_day,
+        COUNT(o.id) FILTER (WHERE o.method = 'Cash' AND o.status = 'Success') as cash_orders,
+        COALESCE(SUM(o.order_price) FILTER (WHERE o.method = 'Cash' AND o.status = 'Success'), 0)::BIGINT as cash_collected,
+        COALESCE((SELECT SUM(h.received_amount) FROM cash_handovers h
+            WHERE h.session_id = s.id AND h.status <> 'Declared'), 0)::BIGINT as handed_over,
+        COALESCE(b.cash, 0) as cash_balance
+    FROM sessions s
+        JOIN couriers c on c.id = s.courier_id
+        LEFT JOIN courier_balances b on b.courier_id = c.id
+        LEFT JOIN orders o on o.session_id = s.id
+    GROUP BY s.id, c.id, b.cash;
+
+create or replace function cash_reconciliation(
+    arg_day DATE
+) returns table (
+    courier_id BIGINT,
+    courier_name VARCHAR,
+    courier_surname VARCHAR,
+    orders_cash BIGINT,
+    ledger_cash BIGINT,
+    handed_over BIGINT,
+    difference BIGINT
+)
+language sql
+as $$
+    SELECT
+        c.id,
+        c.name,
+        c.surname,
+        oc.amount,
+        lc.amount,
+        hc.amount,
+        oc.amount - lc.amount
+    FROM couriers c,
+    LATERAL (SELECT COALESCE(SUM(o.order_price), 0)::BIGINT as amount
+        FROM orders o JOIN sessions s on s.id = o.session_id
+        WHERE s.courier_id = c.id AND o.method = 'Cash' AND o.status = 'Success'
+            AND o.finalize_datetime::DATE = arg_day) oc,
+    LATERAL (SELECT COALESCE(SUM(e.amount), 0)::BIGINT as amount
+        FROM ledger_entries e JOIN ledger_transactions t on t.id = e.transaction_id
+        WHERE e.courier_id = c.id AND e.account = 'CourierCash' AND t.kind = 'CashCollected'
+            AND t.creation_datetime::DATE = arg_day) lc,
+    LATERAL (SELECT COALESCE(SUM(h.received_amount), 0)::BIGINT as amount
+        FROM cash_handovers h
+        WHERE h.courier_id = c.id AND h.status <> 'Declared'
+            AND h.confirm_datetime::DATE = arg_day) hc
+    WHERE oc.amount <> 0 OR lc.amount <> 0 OR hc.amount <> 0;
+$$;
+
 
 CREATE OR REPLACE VIEW restaurant_info AS
     SELECT
         o.id as order_id,
-        o.client_phone,
+        mask_phone(o.client_phone) as client_phone,
         o.client_comment,
         o.order_price,
         o.details,
@@ -550,14 +1328,12 @@ CREATE OR REPLACE VIEW restaurant_info AS
         c.surname as courier_surname,
         c.patronymic as courier_pathronymic,
         c.phone as courier_phone,
-        c.current_rate_amount as courier_rate_amount,
-        c.current_rate_count as courier_rate_count,
         c.picture as courier_picture,
         o.restaurant_id
     FROM orders o
         JOIN sessions s on o.session_id = s.id
         JOIN couriers c on s.courier_id = c.id
-    WHERE status!=ANY('{Success,FailureByCourier,FailureByRestaurant}')
+    WHERE status!=ANY('{Success,FailureByCourier,FailureByRestaurant,Returned}')
     ORDER BY o.creation_datetime;
 
 SELECT * FROM restaurant_info WHERE restaurant_id=$1;
@@ -579,7 +1355,8 @@ CREATE OR REPLACE VIEW
         o.take_datetime,
         o.delivery_datetime,
         cr.politeness as politness_rate,
-        cr.look as look_rate
+        cr.look as look_rate,
+        o.tip
     FROM orders o
         JOIN courier_rating cr on o.id = cr.order_id
         JOIN restaurants r on o.restaurant_id = r.id;
@@ -587,9 +1364,9 @@ CREATE OR REPLACE VIEW
 CREATE OR REPLACE VIEW
     courier_exel AS
     SELECT
-        s.session_day,
-        s.start_time,
-        s.end_real_time,
+        s.start_at::DATE as session_day,
+        s.start_at::TIME as start_time,
+        s.ended_at::TIME as end_real_time,
         o.id as order_id,
         o.take_datetime as take_datetime,
         o.status as order_status,
@@ -597,17 +1374,19 @@ CREATE OR REPLACE VIEW
         o.is_big_order,
         o.cooking_time,
         o.delivery_datetime,
-        o.courier_share - 1500 as courier_salary,
+        COALESCE(p.courier_payout, 0) as courier_salary,
         o.order_price,
         o.delivery_address,
         o.client_comment,
-        o.method
+        o.method,
+        o.tip as courier_tip
     FROM orders o
         JOIN sessions s ON o.session_id = s.id
+        LEFT JOIN order_payouts p ON p.order_id = o.id
     WHERE
-        o.status = ANY('{Success,FailureByCourier,FailureByRestaurant}');
+        o.status = ANY('{Success,FailureByCourier,FailureByRestaurant,Returned}');
 
-DROP VIEW courier_for_curator_exel;
+DROP VIEW IF EXISTS courier_for_curator_exel;
 CREATE OR REPLACE VIEW
 
     courier_for_curator_exel AS
@@ -616,9 +1395,9 @@ CREATE OR REPLACE VIEW
         c.name,
         c.surname,
         c.patronymic,
-        s.session_day,
-        s.start_time,
-        s.end_real_time,
+        s.start_at::DATE as session_day,
+        s.start_at::TIME as start_time,
+        s.ended_at::TIME as end_real_time,
         o.id as order_id,
         o.take_datetime as take_datetime,
         o.status as order_status,
@@ -626,17 +1405,20 @@ CREATE OR REPLACE VIEW
         o.is_big_order,
         o.cooking_time,
         o.delivery_datetime,
-        o.courier_share - 1500 as courier_salary,
+        COALESCE(p.courier_payout, 0) as courier_salary,
         o.courier_share as delivery_cost,
         o.order_price,
         o.delivery_address,
         o.client_comment,
-        o.method
+        o.method,
+        o.tip as courier_tip,
+        mask_phone(o.client_phone) as client_phone
     FROM orders o
         JOIN sessions s ON o.session_id = s.id
         JOIN couriers c ON c.id = s.courier_id
+        LEFT JOIN order_payouts p ON p.order_id = o.id
     WHERE
-        o.status = ANY('{Success,FailureByCourier,FailureByRestaurant}');
+        o.status = ANY('{Success,FailureByCourier,FailureByRestaurant,Returned}');
 
 DROP VIEW  restaurant_exel;
 CREATE OR REPLACE VIEW
@@ -652,11 +1434,12 @@ CREATE OR REPLACE VIEW
         o.order_price,
         o.delivery_address,
         o.client_comment,
-        o.client_phone,
+        mask_phone(o.client_phone) as client_phone,
         o.method
     FROM orders o
     WHERE
-        o.status = ANY('{Success,FailureByCourier,FailureByRestaurant}');
+        o.status = ANY('{Success,FailureByCourier,FailureByRestaurant,Returned}')
+        AND NOT o.is_split;
 
 CREATE OR REPLACE VIEW
     restaurant_for_curator_exel AS
@@ -674,11 +1457,10 @@ CREATE OR REPLACE VIEW
         o.order_price,
         o.delivery_address,
         o.client_comment,
-        o.client_phone,
+        mask_phone(o.client_phone) as client_phone,
         o.method
     FROM orders o
         JOIN restaurants r ON r.id = o.restaurant_id
     WHERE
-        o.status = ANY('{Success,FailureByCourier,FailureByRestaurant}');
-
-
+        o.status = ANY('{Success,FailureByCourier,FailureByRestaurant,Returned}')
+        AND NOT o.is_split;
@@ -0,0 +1,261 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use r2d2_redis::redis;
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::sql_types::*;
+
+use crate::schema::{
+    courier_availability_log,
+    couriers,
+    orders,
+    sessions,
+};
+use crate::enum_types::*;
+use crate::ordering::db::active_statuses;
+use crate::breaks::db::SessionBreak;
+use crate::temp::db::get_courier_coords;
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct AvailabilityChange {
+    pub id: i64,
+    pub courier_id: i64,
+    pub status: AvailabilityStatus,
+    pub session_id: Option<i64>,
+    pub started_at: chrono::NaiveDateTime,
+    /// none for the current status
+    pub ended_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct CourierAvailability {
+    pub courier_id: i64,
+    pub status: AvailabilityStatus,
+    pub session_id: Option<i64>,
+    /// none for a courier who has never been online
+    pub since: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct AvailabilityFilter {
+    #[serde(default)]
+    pub status: Option<AvailabilityStatus>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct CourierPeriod {
+    pub courier_id: i64,
+    pub from: chrono::NaiveDateTime,
+    pub till: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Period {
+    pub from: chrono::NaiveDateTime,
+    pub till: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct Utilisation {
+    #[sql_type="Bigint"]
+    pub courier_id: i64,
+    #[sql_type="Timestamp"]
+    pub hour: chrono::NaiveDateTime,
+    /// every status but offline
+    #[sql_type="Bigint"]
+    pub online_seconds: i64,
+    /// offered or carrying orders
+    #[sql_type="Bigint"]
+    pub busy_seconds: i64,
+    #[sql_type="Bigint"]
+    pub paused_seconds: i64,
+}
+
+impl CourierAvailability {
+    /// the only place the courier's status is worked out: the open session,
+    /// then the break, the orders on hand and the age of the last position
+    pub async fn compute(
+        courier_id: i64,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<(AvailabilityStatus, Option<i64>)> {
+        let session_id = sessions::table
+            .filter(sessions::courier_id.eq(courier_id))
+            .filter(sessions::ended_at.is_null())
+            .select(sessions::id)
+            .first::<i64>(conn)
+            .optional()?;
+        let session_id = match session_id {
+            Some(id) => id,
+            None => return Ok((AvailabilityStatus::Offline, None)),
+        };
+        if SessionBreak::is_paused(courier_id, conn).await? {
+            return Ok((AvailabilityStatus::Paused, Some(session_id)));
+        }
+        let on_hand = orders::table
+            .filter(orders::session_id.eq(session_id))
+            .filter(orders::status.eq_any(active_statuses()))
+            .select(orders::status)
+            .get_results::<OrderStatus>(conn)?;
+        if on_hand.iter().any(|s| *s != OrderStatus::CourierConfirmation) {
+            return Ok((AvailabilityStatus::Busy, Some(session_id)));
+        }
+        if !on_hand.is_empty() {
+            return Ok((AvailabilityStatus::Offered, Some(session_id)));
+        }
+        let fresh = get_courier_coords(courier_id, conn_redis).await?
+            .map(|c| !c.is_stale())
+            .unwrap_or(false);
+        if !fresh {
+            return Ok((AvailabilityStatus::StaleLocation, Some(session_id)));
+        }
+        Ok((AvailabilityStatus::OnlineIdle, Some(session_id)))
+    }
+
+    /// recomputes the status and records it if it changed
+    pub async fn refresh(
+        courier_id: i64,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Self> {
+        conn.transaction::<Self, ApiError, _>(|| futures::executor::block_on(async {
+            // concurrent refreshes of one courier queue up on the courier row and
+            // work the status out only once they hold it, so a later refresh
+            // always records the later status
+            couriers::table
+                .filter(couriers::id.eq(courier_id))
+                .select(couriers::id)
+                .for_update()
+                .get_result::<i64>(conn)?;
+            let (status, session_id) = Self::compute(courier_id, conn, conn_redis).await?;
+            let current = courier_availability_log::table
+                .filter(courier_availability_log::courier_id.eq(courier_id))
+                .filter(courier_availability_log::ended_at.is_null())
+                .first::<AvailabilityChange>(conn)
+                .optional()?;
+            let since = match &current {
+                Some(c) if c.status == status => Some(c.started_at),
+                None if status == AvailabilityStatus::Offline => None,
+                _ => {
+                    diesel::update(courier_availability_log::table
+                            .filter(courier_availability_log::courier_id.eq(courier_id))
+                            .filter(courier_availability_log::ended_at.is_null()))
+                        .set(courier_availability_log::ended_at.eq(diesel::dsl::now.nullable()))
+                        .execute(conn)?;
+                    let r = diesel::insert_into(courier_availability_log::table)
+                        .values((
+                            courier_availability_log::courier_id.eq(courier_id),
+                            courier_availability_log::status.eq(status),
+                            courier_availability_log::session_id.eq(session_id),
+                        ))
+                        .get_result::<AvailabilityChange>(conn)?;
+                    Some(r.started_at)
+                },
+            };
+            Ok(Self {
+                courier_id,
+                status,
+                session_id,
+                since,
+            })
+        }))
+    }
+
+    /// refreshes the status after a courier's action, a failure is only logged
+    pub async fn touch(
+        courier_id: i64,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) {
+        if let Err(e) = Self::refresh(courier_id, conn, conn_redis).await {
+            println!("availability refresh failed: {}", e.message);
+        }
+    }
+
+    /// couriers on a session and those whose last recorded status is not offline yet
+    pub async fn refresh_all(
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Vec<Self>> {
+        let mut ids = sessions::table
+            .filter(sessions::ended_at.is_null())
+            .select(sessions::courier_id)
+            .get_results::<i64>(conn)?;
+        ids.extend(courier_availability_log::table
+            .filter(courier_availability_log::ended_at.is_null())
+            .filter(courier_availability_log::status.ne(AvailabilityStatus::Offline))
+            .select(courier_availability_log::courier_id)
+            .get_results::<i64>(conn)?);
+        ids.sort();
+        ids.dedup();
+        let mut r = vec![];
+        for id in ids {
+            r.push(Self::refresh(id, conn, conn_redis).await?);
+        }
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        data: &AvailabilityFilter,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Vec<Self>> {
+        let r = Self::refresh_all(conn, conn_redis).await?
+            .into_iter()
+            .filter(|a| data.status.map(|s| s == a.status).unwrap_or(true))
+            .collect();
+        Ok(r)
+    }
+
+    /// idle couriers with a fresh position are offered orders, and so are
+    /// couriers carrying one, who may take a second order on the way
+    pub fn require_dispatchable(&self) -> Result<()> {
+        let message = match self.status {
+            AvailabilityStatus::OnlineIdle |
+            AvailabilityStatus::Busy => return Ok(()),
+            AvailabilityStatus::Offline => "no open session",
+            AvailabilityStatus::Paused => "you are on a break",
+            AvailabilityStatus::Offered => "answer the current offer first",
+            AvailabilityStatus::StaleLocation => "location is outdated, send it again",
+        };
+        Err(ApiError {
+            code: 400,
+            message: message.to_string(),
+            error_type: ErrorType::InternalError,
+        })
+    }
+}
+
+impl AvailabilityChange {
+    pub async fn get_history(
+        data: &CourierPeriod,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = courier_availability_log::table
+            .filter(courier_availability_log::courier_id.eq(data.courier_id))
+            .filter(courier_availability_log::started_at.lt(data.till))
+            .filter(courier_availability_log::ended_at.is_null()
+                .or(courier_availability_log::ended_at.gt(data.from)))
+            .order(courier_availability_log::started_at)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl Utilisation {
+    pub async fn get(
+        data: &Period,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        require!(data.from < data.till, "period ends before it starts");
+        let r = diesel::sql_query("select * from courier_utilisation($1,$2);")
+            .bind::<Timestamp,_>(data.from)
+            .bind::<Timestamp,_>(data.till)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
@@ -0,0 +1,97 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use r2d2_redis::RedisConnectionManager;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type RedisDbPool = r2d2::Pool<RedisConnectionManager>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use super::db::{
+    CourierAvailability,
+    AvailabilityChange,
+    AvailabilityFilter,
+    CourierPeriod,
+    Period,
+    Utilisation,
+};
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+pub fn availability_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/availability")
+        .route("/get", web::post().to(get))
+        .route("/get_all", web::post().to(get_all))
+        .route("/get_history", web::post().to(get_history))
+        .route("/get_utilisation", web::post().to(get_utilisation))
+    );
+}
+
+fn is_staff(auth: &Auth) -> bool {
+    auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string())
+}
+
+pub async fn get(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth) || (auth.roles.contains(&"courier".to_string()) && auth.id == form.id),
+        "not permitted");
+    let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
+    let r = CourierAvailability::refresh(form.id, &conn, &mut conn_redis).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_all(
+    auth: Auth,
+    form: web::Json<AvailabilityFilter>,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted");
+    let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
+    let r = CourierAvailability::get_all(&form, &conn, &mut conn_redis).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_history(
+    auth: Auth,
+    form: web::Json<CourierPeriod>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted");
+    let conn = conn.get()?;
+    let r = AvailabilityChange::get_history(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_utilisation(
+    auth: Auth,
+    form: web::Json<Period>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted");
+    let conn = conn.get()?;
+    let r = Utilisation::get(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
@@ -37,6 +37,16 @@ use topgo::schema::{
     restaurants_for_curators_xls_reports,
 };
 use topgo::enum_types::*;
+use topgo::ordering::db::Sessions;
+use topgo::breaks::db::SessionBreak;
+use topgo::availability::db::CourierAvailability;
+use topgo::temp::db::{
+    LocationPoint,
+    location_retention_days,
+    cleanup_coords,
+    rm_coords,
+};
+use r2d2_redis::redis;
 
 #[macro_use]
 extern crate diesel;
@@ -138,6 +148,8 @@ pub struct CourierXLS {
     pub client_comment: String,
     #[sql_type="Paymethod"]
     pub method: PayMethod,
+    #[sql_type="Bigint"]
+    pub courier_tip: i64,
 }
 
 #[derive(Serialize,Deserialize,Clone,QueryableByName,Debug)]
@@ -186,6 +198,8 @@ pub struct CourierXLSTotal {
     pub client_phone: String,
     #[sql_type="Paymethod"]
     pub method: PayMethod,
+    #[sql_type="Bigint"]
+    pub courier_tip: i64,
 }
 
 #[derive(Serialize,Deserialize,Clone,QueryableByName,Debug)]
@@ -279,6 +293,14 @@ async fn main() {
         proc_couriers_for_curators(p.clone());
     }).unwrap()).unwrap();
 
+    let p = pool.clone();
+    sched.add(Job::new("0 30 3 * * * *", move |_uuid, _l| {
+        let conn = p.get().unwrap();
+        let days = location_retention_days();
+        let n = futures::executor::block_on(LocationPoint::purge(days, &conn)).unwrap();
+        println!("purged {} location points older than {} days", n, days);
+    }).unwrap()).unwrap();
+
     let p = pool.clone();
     sched.add(Job::new("0 0 0 * * Mon *", move |_uuid, _l| {
         println!("every week task");
@@ -291,6 +313,41 @@ async fn main() {
             .execute(&p.get().unwrap()).unwrap();
     }).unwrap()).unwrap();
 
+    let p = pool.clone();
+    sched.add(Job::new("0 * * * * * *", move |_uuid, _l| {
+        diesel::sql_query("select * from process_no_shows();")
+            .execute(&p.get().unwrap()).unwrap();
+    }).unwrap()).unwrap();
+
+    let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
+    let redis_client = redis::Client::open(redis_url).expect("Failed to open redis.");
+
+    let p = pool.clone();
+    let r = redis_client.clone();
+    sched.add(Job::new("30 * * * * * *", move |_uuid, _l| {
+        let conn = p.get().unwrap();
+        let mut conn_redis = r.get_connection().unwrap();
+        futures::executor::block_on(async {
+            Sessions::warn_ending(&conn).await.unwrap();
+            // couriers let go leave the map and go offline right away
+            for courier_id in Sessions::close_expired(&conn).await.unwrap() {
+                rm_coords(courier_id, &mut conn_redis).await.unwrap();
+                CourierAvailability::touch(courier_id, &conn, &mut conn_redis).await;
+            }
+            SessionBreak::resume_overdue(&conn).await.unwrap();
+        });
+    }).unwrap()).unwrap();
+
+    let p = pool.clone();
+    sched.add(Job::new("45 * * * * * *", move |_uuid, _l| {
+        let conn = p.get().unwrap();
+        let mut conn_redis = redis_client.get_connection().unwrap();
+        futures::executor::block_on(async {
+            cleanup_coords(&conn, &mut conn_redis).await.unwrap();
+            CourierAvailability::refresh_all(&conn, &mut conn_redis).await.unwrap();
+        });
+    }).unwrap()).unwrap();
+
     sched.start().await.unwrap();
 }
 
@@ -324,6 +381,7 @@ fn proc_couriers_for_them(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>
                 sheet1.write_string(0, 11, "адрес доставки", None).unwrap();
                 sheet1.write_string(0, 12, "комментарий клиента", None).unwrap();
                 sheet1.write_string(0, 13, "способ оплаты", None).unwrap(); 
+                sheet1.write_string(0, 14, "чаевые", None).unwrap();
                 let i = ((i_it % 100) + 1) as u32;
                 let ind = i_it as usize;
                 sheet1.write_string(i, 0, &rows[ind].session_day.to_string(), None).unwrap();
@@ -338,6 +396,7 @@ fn proc_couriers_for_them(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>
                     OrderStatus::Success => "успешно доставлено",
                     OrderStatus::FailureByRestaurant => "отменено по вине ресторана",
                     OrderStatus::FailureByCourier => "отменено по вине курьера",
+                    OrderStatus::Returned => "возвращено в ресторан",
                     _ => panic!(),
                 }, None).unwrap();
                 sheet1.write_string(i, 6, &rows[ind].details, None).unwrap();
@@ -353,6 +412,7 @@ fn proc_couriers_for_them(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>
                     PayMethod::Card =>"картой",
                     PayMethod::AlreadyPayed =>"оплачено заранее",
                 }, None).unwrap();
+                sheet1.write_number(i, 14, (rows[ind].courier_tip / 100) as f64, None).unwrap();
                 if i == 100 || ind as usize == rows.len()-1 {
                     workbook.close().expect("workbook can be closed");
                     send_file(
@@ -397,6 +457,7 @@ fn proc_couriers_for_curators(pool: Arc<r2d2::Pool<ConnectionManager<PgConnectio
                 sheet1.write_string(0, 17, "телефон клиента", None).unwrap();
                 sheet1.write_string(0, 18, "комментарий клиента", None).unwrap();
                 sheet1.write_string(0, 19, "способ оплаты", None).unwrap(); 
+                sheet1.write_string(0, 20, "чаевые", None).unwrap();
                 let i = ((i_it % 100) + 1) as u32;
                 let ind = i_it as usize;
                 sheet1.write_string(i, 0, &rows[ind].phone.to_string(), None).unwrap();
@@ -415,6 +476,7 @@ fn proc_couriers_for_curators(pool: Arc<r2d2::Pool<ConnectionManager<PgConnectio
                     OrderStatus::Success => "успешно доставлено",
                     OrderStatus::FailureByRestaurant => "отменено по вине ресторана",
                     OrderStatus::FailureByCourier => "отменено по вине курьера",
+                    OrderStatus::Returned => "возвращено в ресторан",
                     _ => panic!(),
                 }, None).unwrap();
                 sheet1.write_string(i, 10, &rows[ind].details, None).unwrap();
@@ -432,6 +494,7 @@ fn proc_couriers_for_curators(pool: Arc<r2d2::Pool<ConnectionManager<PgConnectio
                     PayMethod::Card =>"картой",
                     PayMethod::AlreadyPayed =>"оплачено заранее",
                 }, None).unwrap();
+                sheet1.write_number(i, 20, (rows[ind].courier_tip / 100) as f64, None).unwrap();
                 if i == 100 || ind as usize == rows.len()-1 {
                     workbook.close().expect("workbook can be closed");
                     send_file(
@@ -453,6 +516,7 @@ fn proc_restaurants(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>) {
     let restaurant_ids: Vec<(i64,String)> = restaurants::table
         .select((restaurants::id,restaurants::email))
         .get_results::<(i64,String)>(&pool.get().unwrap()).unwrap();
+    // a split order is reported by its parcels, the parent would count it twice
     for id in restaurant_ids {
         let fname = format!("summary/{}.xlsx",Uuid::new_v4());
         let mut workbook = Workbook::new(&fname);
@@ -462,6 +526,7 @@ fn proc_restaurants(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>) {
         let orders_reject_pay = orders::table
                 .filter(orders::status.eq(OrderStatus::FailureByRestaurant))
                 .filter(orders::restaurant_id.eq(id.0))
+                .filter(orders::is_split.eq(false))
                 .filter(dsl::sql("status = ANY('{Success,FailureByCourier,FailureByRestaurant}') AND
          finalize_datetime::DATE < CURRENT_DATE AT TIME ZONE 'Europe/Moscow' AND
          finalize_datetime::DATE >= (CURRENT_DATE-7) AT TIME ZONE 'Europe/Moscow'"))
@@ -473,6 +538,7 @@ fn proc_restaurants(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>) {
          finalize_datetime::DATE < CURRENT_DATE AT TIME ZONE 'Europe/Moscow' AND
          finalize_datetime::DATE >= (CURRENT_DATE-7) AT TIME ZONE 'Europe/Moscow'"))
                 .filter(orders::restaurant_id.eq(id.0))
+                .filter(orders::is_split.eq(false))
                 .select(diesel::dsl::sum(orders::order_price))
                 .get_result::<Option<BigDecimal>>(&pool.get().unwrap())
                 .unwrap().unwrap_or(BigDecimal::zero());
@@ -482,6 +548,7 @@ fn proc_restaurants(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>) {
          finalize_datetime::DATE < CURRENT_DATE AT TIME ZONE 'Europe/Moscow' AND
          finalize_datetime::DATE >= (CURRENT_DATE-7) AT TIME ZONE 'Europe/Moscow'"))
                 .filter(orders::restaurant_id.eq(id.0))
+                .filter(orders::is_split.eq(false))
                 .select(diesel::dsl::sum(orders::order_price))
                 .get_result::<Option<BigDecimal>>(&pool.get().unwrap())
                 .unwrap().unwrap_or(BigDecimal::zero());
@@ -491,6 +558,7 @@ fn proc_restaurants(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>) {
          finalize_datetime::DATE < CURRENT_DATE AT TIME ZONE 'Europe/Moscow' AND
          finalize_datetime::DATE >= (CURRENT_DATE-7) AT TIME ZONE 'Europe/Moscow'"))
                 .filter(orders::restaurant_id.eq(id.0))
+                .filter(orders::is_split.eq(false))
                 .select(diesel::dsl::sum(orders::order_price))
                 .get_result::<Option<BigDecimal>>(&pool.get().unwrap())
                 .unwrap().unwrap_or(BigDecimal::zero());
@@ -500,6 +568,7 @@ fn proc_restaurants(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>) {
          finalize_datetime::DATE < CURRENT_DATE AT TIME ZONE 'Europe/Moscow' AND
          finalize_datetime::DATE >= (CURRENT_DATE-7) AT TIME ZONE 'Europe/Moscow'"))
                 .filter(orders::restaurant_id.eq(id.0))
+                .filter(orders::is_split.eq(false))
                 .count()
                 .execute(&pool.get().unwrap()).unwrap();
         let orders_card_pay = orders::table
@@ -508,6 +577,7 @@ fn proc_restaurants(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>) {
          finalize_datetime::DATE < CURRENT_DATE AT TIME ZONE 'Europe/Moscow' AND
          finalize_datetime::DATE >= (CURRENT_DATE-7) AT TIME ZONE 'Europe/Moscow'"))
                 .filter(orders::restaurant_id.eq(id.0))
+                .filter(orders::is_split.eq(false))
                 .count()
                 .execute(&pool.get().unwrap()).unwrap();
         let orders_no_pay: usize = orders::table
@@ -516,10 +586,12 @@ fn proc_restaurants(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>) {
          finalize_datetime::DATE < CURRENT_DATE AT TIME ZONE 'Europe/Moscow' AND
          finalize_datetime::DATE >= (CURRENT_DATE-7) AT TIME ZONE 'Europe/Moscow'"))
                 .filter(orders::restaurant_id.eq(id.0))
+                .filter(orders::is_split.eq(false))
                 .count()
                 .execute(&pool.get().unwrap()).unwrap();
         let total_delivery: usize = orders::table
                 .filter(orders::restaurant_id.eq(id.0))
+                .filter(orders::is_split.eq(false))
                 .filter(dsl::sql("status = ANY('{Success,FailureByCourier,FailureByRestaurant}') AND
          finalize_datetime::DATE < CURRENT_DATE AT TIME ZONE 'Europe/Moscow' AND
          finalize_datetime::DATE >= (CURRENT_DATE-7) AT TIME ZONE 'Europe/Moscow'"))
@@ -0,0 +1,293 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use r2d2_redis::redis;
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::sql_types::*;
+use diesel::dsl::IntervalDsl;
+
+use crate::schema::{
+    break_rules,
+    notifications,
+    session_breaks,
+    sessions,
+};
+use crate::ordering::db::Sessions;
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct BreakRule {
+    pub id: i64,
+    /// breaks allowed per session
+    pub max_breaks: i16,
+    /// length of one break, the courier is resumed once it runs out
+    pub max_minutes: i32,
+    pub effective_from: chrono::NaiveDateTime,
+    pub admin_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="break_rules"]
+pub struct NewBreakRule {
+    pub max_breaks: i16,
+    pub max_minutes: i32,
+    pub effective_from: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct SessionBreak {
+    pub id: i64,
+    pub session_id: i64,
+    pub courier_id: i64,
+    pub started_at: chrono::NaiveDateTime,
+    pub ended_at: Option<chrono::NaiveDateTime>,
+    /// ended by the scheduler after the allowed length
+    pub auto_resumed: bool,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct BreakStatus {
+    pub current: Option<SessionBreak>,
+    pub breaks_taken: i64,
+    pub rule: Option<BreakRule>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Period {
+    pub from: chrono::NaiveDate,
+    pub till: chrono::NaiveDate,
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct BreakReport {
+    #[sql_type="Bigint"]
+    pub session_id: i64,
+    #[sql_type="Bigint"]
+    pub courier_id: i64,
+    #[sql_type="Varchar"]
+    pub courier_name: String,
+    #[sql_type="Varchar"]
+    pub courier_surname: String,
+    #[sql_type="Date"]
+    pub session_day: chrono::NaiveDate,
+    #[sql_type="Bigint"]
+    pub breaks: i64,
+    #[sql_type="Bigint"]
+    pub auto_resumed: i64,
+    #[sql_type="Bigint"]
+    pub break_minutes: i64,
+}
+
+impl BreakRule {
+    pub async fn new(
+        data: &NewBreakRule,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(data.max_breaks >= 0, "number of breaks can't be negative");
+        require!(data.max_minutes > 0, "break length must be positive");
+        let r = diesel::insert_into(break_rules::table)
+            .values((data, break_rules::admin_id.eq(admin_id)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = break_rules::table
+            .order(break_rules::effective_from.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// the rule in force for a session started at `at`
+    pub async fn effective(
+        at: chrono::NaiveDateTime,
+        conn: &PgConnection,
+    ) -> Result<Option<Self>> {
+        let r = break_rules::table
+            .filter(break_rules::effective_from.le(at))
+            .order(break_rules::effective_from.desc())
+            .first::<Self>(conn)
+            .optional()?;
+        Ok(r)
+    }
+}
+
+impl SessionBreak {
+    pub async fn current(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Option<Self>> {
+        let r = session_breaks::table
+            .filter(session_breaks::courier_id.eq(courier_id))
+            .filter(session_breaks::ended_at.is_null())
+            .first::<Self>(conn)
+            .optional()?;
+        Ok(r)
+    }
+
+    pub async fn is_paused(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<bool> {
+        Ok(Self::current(courier_id, conn).await?.is_some())
+    }
+
+    /// pauses the courier's open session; the courier drops off the map
+    /// and gets no offers until resumed
+    pub async fn pause(
+        courier_id: i64,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Self> {
+        use crate::temp::db::rm_coords;
+        let session = sessions::table
+            .filter(sessions::courier_id.eq(courier_id))
+            .filter(sessions::ended_at.is_null())
+            .first::<Sessions>(conn)
+            .optional()?
+            .ok_or(ApiError {
+                code: 400,
+                message: "no open session".to_string(),
+                error_type: ErrorType::InternalError,
+            })?;
+        require!(!Self::is_paused(courier_id, conn).await?, "already on a break");
+        require!(!Sessions::has_active_orders(session.id, conn).await?, "finish your orders first");
+        let rule = BreakRule::effective(session.start_at, conn).await?.ok_or(ApiError {
+            code: 400,
+            message: "breaks are not allowed".to_string(),
+            error_type: ErrorType::InternalError,
+        })?;
+        let taken = session_breaks::table
+            .filter(session_breaks::session_id.eq(session.id))
+            .count()
+            .get_result::<i64>(conn)?;
+        require!(taken < rule.max_breaks as i64, "no breaks left for this session");
+        let r = diesel::insert_into(session_breaks::table)
+            .values((
+                session_breaks::session_id.eq(session.id),
+                session_breaks::courier_id.eq(courier_id),
+            ))
+            .get_result::<Self>(conn)?;
+        rm_coords(courier_id, conn_redis).await?;
+        Ok(r)
+    }
+
+    pub async fn resume(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::update(session_breaks::table
+                .filter(session_breaks::courier_id.eq(courier_id))
+                .filter(session_breaks::ended_at.is_null()))
+            .set(session_breaks::ended_at.eq(diesel::dsl::now.nullable()))
+            .get_result::<Self>(conn)
+            .optional()?;
+        r.ok_or(ApiError {
+            code: 400,
+            message: "not on a break".to_string(),
+            error_type: ErrorType::InternalError,
+        })
+    }
+
+    pub async fn status(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<BreakStatus> {
+        let session = sessions::table
+            .filter(sessions::courier_id.eq(courier_id))
+            .filter(sessions::ended_at.is_null())
+            .first::<Sessions>(conn)
+            .optional()?;
+        let (breaks_taken, rule) = match &session {
+            Some(s) => (
+                session_breaks::table
+                    .filter(session_breaks::session_id.eq(s.id))
+                    .count()
+                    .get_result::<i64>(conn)?,
+                BreakRule::effective(s.start_at, conn).await?,
+            ),
+            None => (0, None),
+        };
+        Ok(BreakStatus {
+            current: Self::current(courier_id, conn).await?,
+            breaks_taken,
+            rule,
+        })
+    }
+
+    pub async fn get_by_session(
+        session_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = session_breaks::table
+            .filter(session_breaks::session_id.eq(session_id))
+            .order(session_breaks::started_at)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// ends breaks that ran out of the allowed length, telling the courier,
+    /// and those left open in a closed session
+    pub async fn resume_overdue(
+        conn: &PgConnection,
+    ) -> Result<usize> {
+        let open = session_breaks::table
+            .inner_join(sessions::table)
+            .filter(session_breaks::ended_at.is_null())
+            .get_results::<(Self, Sessions)>(conn)?;
+        let mut r = 0;
+        for (b, s) in open.iter() {
+            if s.ended_at.is_some() {
+                diesel::update(session_breaks::table.filter(session_breaks::id.eq(b.id)))
+                    .set(session_breaks::ended_at.eq(s.ended_at))
+                    .execute(conn)?;
+                r += 1;
+                continue;
+            }
+            let max_minutes = match BreakRule::effective(s.start_at, conn).await? {
+                Some(rule) => rule.max_minutes,
+                None => 0,
+            };
+            let overdue = diesel::update(session_breaks::table
+                    .filter(session_breaks::id.eq(b.id))
+                    .filter(session_breaks::started_at.le(diesel::dsl::now - max_minutes.minutes())))
+                .set((
+                    session_breaks::ended_at.eq(diesel::dsl::now.nullable()),
+                    session_breaks::auto_resumed.eq(true),
+                ))
+                .execute(conn)?;
+            if overdue > 0 {
+                diesel::insert_into(notifications::table)
+                    .values((
+                        notifications::title.eq("Перерыв окончен"),
+                        notifications::message.eq("Перерыв закончился, вы снова на линии"),
+                        notifications::courier_id.eq(b.courier_id),
+                    ))
+                    .execute(conn)?;
+                r += 1;
+            }
+        }
+        Ok(r)
+    }
+}
+
+impl BreakReport {
+    pub async fn get(
+        data: &Period,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = diesel::sql_query("select * from session_break_report
+            WHERE session_day BETWEEN $1 AND $2 ORDER BY session_day, courier_id;")
+            .bind::<Date,_>(data.from)
+            .bind::<Date,_>(data.till)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
@@ -0,0 +1,130 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use r2d2_redis::RedisConnectionManager;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type RedisDbPool = r2d2::Pool<RedisConnectionManager>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use super::db::{
+    BreakRule,
+    NewBreakRule,
+    SessionBreak,
+    BreakReport,
+    Period,
+};
+use crate::availability::db::CourierAvailability;
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+pub fn breaks_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/breaks")
+        .route("/pause", web::post().to(pause))
+        .route("/resume", web::post().to(resume))
+        .route("/get_status", web::post().to(get_status))
+        .route("/get_by_session", web::post().to(get_by_session))
+        .route("/get_report", web::post().to(get_report))
+        .route("/new_rule", web::post().to(new_rule))
+        .route("/get_rules", web::post().to(get_rules))
+    );
+}
+
+fn is_staff(auth: &Auth) -> bool {
+    auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string())
+}
+
+pub async fn pause(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted");
+    let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
+    let r = SessionBreak::pause(auth.id, &conn, &mut conn_redis).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut conn_redis).await;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn resume(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted");
+    let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
+    let r = SessionBreak::resume(auth.id, &conn).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut conn_redis).await;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_status(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted");
+    let conn = conn.get()?;
+    let r = SessionBreak::status(auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_by_session(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted");
+    let conn = conn.get()?;
+    let r = SessionBreak::get_by_session(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_report(
+    auth: Auth,
+    form: web::Json<Period>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted");
+    let conn = conn.get()?;
+    let r = BreakReport::get(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn new_rule(
+    auth: Auth,
+    form: web::Json<NewBreakRule>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin");
+    let conn = conn.get()?;
+    let r = BreakRule::new(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_rules(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted");
+    let conn = conn.get()?;
+    let r = BreakRule::get_all(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
@@ -0,0 +1,310 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use crate::schema::{
+    chat_templates,
+    order_message_reads,
+    order_messages,
+};
+use crate::ordering::db::Orders;
+
+const MAX_MESSAGE_LEN: usize = 2000;
+
+/// who reads or writes the thread
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Participant {
+    pub role: String,
+    pub id: i64,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct OrderMessage {
+    pub id: i64,
+    pub order_id: i64,
+    pub author_role: String,
+    pub author_id: i64,
+    pub body: String,
+    pub template_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+/// either a free text or one of the quick replies
+#[derive(Serialize,Deserialize,Clone)]
+pub struct NewOrderMessage {
+    pub order_id: i64,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub template_id: Option<i64>,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct ReadMarker {
+    pub order_id: i64,
+    pub reader_role: String,
+    pub reader_id: i64,
+    pub last_read_id: i64,
+    pub read_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Thread {
+    pub messages: Vec<OrderMessage>,
+    pub read_markers: Vec<ReadMarker>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Unread {
+    pub order_id: i64,
+    pub count: i64,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct ChatTemplate {
+    pub id: i64,
+    /// none means the template is offered to everybody
+    pub role: Option<String>,
+    pub body: String,
+    pub is_active: bool,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="chat_templates"]
+pub struct NewChatTemplate {
+    pub role: Option<String>,
+    pub body: String,
+}
+
+impl Participant {
+    pub fn is_staff(&self) -> bool {
+        self.role == "curator" || self.role == "admin"
+    }
+
+    /// the owning restaurant, the courier carrying the order and the staff
+    pub async fn can_access(
+        &self,
+        order: &Orders,
+        conn: &PgConnection,
+    ) -> Result<bool> {
+        match self.role.as_str() {
+            "curator" | "admin" => Ok(true),
+            "restaurant" => Ok(order.restaurant_id == Some(self.id)),
+            "courier" => order.is_carried_by(self.id, conn).await,
+            _ => Ok(false),
+        }
+    }
+
+    async fn require_access(
+        &self,
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Orders> {
+        let order = Orders::get_by_id(order_id, conn).await?;
+        require!(self.can_access(&order, conn).await?, "not your order");
+        Ok(order)
+    }
+}
+
+impl OrderMessage {
+    pub async fn send(
+        data: &NewOrderMessage,
+        author: &Participant,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let order = author.require_access(data.order_id, conn).await?;
+        require!(author.is_staff() || order.finalize_datetime.is_none(), "order is already finalized");
+        let body = match (data.template_id, &data.body) {
+            (Some(id), _) => {
+                let t = ChatTemplate::get(id, conn).await?;
+                require!(t.is_active, "template is not active");
+                require!(t.role.as_ref().map(|r| *r == author.role).unwrap_or(true),
+                    "template is not for your role");
+                t.body
+            },
+            (None, Some(body)) => body.trim().to_string(),
+            (None, None) => String::new(),
+        };
+        require!(!body.is_empty(), "message is empty");
+        require!(body.chars().count() <= MAX_MESSAGE_LEN, "message is too long");
+        let r = diesel::insert_into(order_messages::table)
+            .values((
+                order_messages::order_id.eq(order.id),
+                order_messages::author_role.eq(&author.role),
+                order_messages::author_id.eq(author.id),
+                order_messages::body.eq(&body),
+                order_messages::template_id.eq(data.template_id),
+            ))
+            .get_result::<Self>(conn)?;
+        // the author has obviously read everything up to their own message
+        ReadMarker::mark(order.id, r.id, author, conn).await?;
+        Ok(r)
+    }
+
+    /// messages newer than `after_id`, oldest first
+    pub async fn get_after(
+        order_id: i64,
+        after_id: i64,
+        reader: &Participant,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        reader.require_access(order_id, conn).await?;
+        let r = order_messages::table
+            .filter(order_messages::order_id.eq(order_id))
+            .filter(order_messages::id.gt(after_id))
+            .order(order_messages::id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_thread(
+        order_id: i64,
+        reader: &Participant,
+        conn: &PgConnection,
+    ) -> Result<Thread> {
+        let messages = Self::get_after(order_id, 0, reader, conn).await?;
+        let read_markers = order_message_reads::table
+            .filter(order_message_reads::order_id.eq(order_id))
+            .get_results::<ReadMarker>(conn)?;
+        Ok(Thread {
+            messages,
+            read_markers,
+        })
+    }
+}
+
+impl ReadMarker {
+    /// moves the reader's marker forward, never back
+    pub async fn mark(
+        order_id: i64,
+        message_id: i64,
+        reader: &Participant,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        reader.require_access(order_id, conn).await?;
+        let exists = order_messages::table
+            .filter(order_messages::id.eq(message_id))
+            .filter(order_messages::order_id.eq(order_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        require!(exists > 0, "message not found");
+        let r = diesel::insert_into(order_message_reads::table)
+            .values((
+                order_message_reads::order_id.eq(order_id),
+                order_message_reads::reader_role.eq(&reader.role),
+                order_message_reads::reader_id.eq(reader.id),
+                order_message_reads::last_read_id.eq(message_id),
+            ))
+            .on_conflict((
+                order_message_reads::order_id,
+                order_message_reads::reader_role,
+                order_message_reads::reader_id,
+            ))
+            .do_update()
+            .set((
+                order_message_reads::last_read_id.eq(diesel::dsl::sql::<diesel::sql_types::Bigint>(
+                    "GREATEST(order_message_reads.last_read_id, excluded.last_read_id)")),
+                order_message_reads::read_datetime.eq(diesel::dsl::now),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// unread messages written by others, for the orders the reader can see
+    pub async fn unread(
+        order_ids: &[i64],
+        reader: &Participant,
+        conn: &PgConnection,
+    ) -> Result<Vec<Unread>> {
+        let mut r = vec![];
+        for order_id in order_ids {
+            reader.require_access(*order_id, conn).await?;
+            let last_read_id = order_message_reads::table
+                .filter(order_message_reads::order_id.eq(order_id))
+                .filter(order_message_reads::reader_role.eq(&reader.role))
+                .filter(order_message_reads::reader_id.eq(reader.id))
+                .select(order_message_reads::last_read_id)
+                .get_result::<i64>(conn)
+                .optional()?
+                .unwrap_or(0);
+            let count = order_messages::table
+                .filter(order_messages::order_id.eq(order_id))
+                .filter(order_messages::id.gt(last_read_id))
+                .filter(order_messages::author_role.ne(&reader.role)
+                    .or(order_messages::author_id.ne(reader.id)))
+                .count()
+                .get_result::<i64>(conn)?;
+            r.push(Unread {
+                order_id: *order_id,
+                count,
+            });
+        }
+        Ok(r)
+    }
+}
+
+impl ChatTemplate {
+    pub async fn new(
+        data: &NewChatTemplate,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(!data.body.trim().is_empty(), "template is empty");
+        let r = diesel::insert_into(chat_templates::table)
+            .values(data)
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn toggle(
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::update(chat_templates::table.filter(chat_templates::id.eq(id)))
+            .set(chat_templates::is_active.eq(diesel::dsl::not(chat_templates::is_active)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get(
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = chat_templates::table
+            .filter(chat_templates::id.eq(id))
+            .get_result::<Self>(conn)
+            .optional()?;
+        r.ok_or(ApiError {
+            code: 404,
+            message: "template not found".to_string(),
+            error_type: ErrorType::InternalError,
+        })
+    }
+
+    /// active templates offered to the role
+    pub async fn get_for_role(
+        role: &str,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = chat_templates::table
+            .filter(chat_templates::is_active.eq(true))
+            .filter(chat_templates::role.is_null().or(chat_templates::role.eq(role)))
+            .order(chat_templates::id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = chat_templates::table
+            .order(chat_templates::id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
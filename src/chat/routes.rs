@@ -0,0 +1,192 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use super::db::{
+    Participant,
+    OrderMessage,
+    NewOrderMessage,
+    ReadMarker,
+    ChatTemplate,
+    NewChatTemplate,
+};
+
+/// longest time a poll request is held open
+const POLL_TIMEOUT_SECS: u64 = 25;
+const POLL_INTERVAL_MILLIS: u64 = 1000;
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct Poll {
+    order_id: i64,
+    #[serde(default)]
+    after_id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct Read {
+    order_id: i64,
+    message_id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct OrderIds {
+    order_ids: Vec<i64>,
+}
+
+pub fn chat_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/chat")
+        .route("/send", web::post().to(send))
+        .route("/get_thread", web::post().to(get_thread))
+        .route("/poll", web::post().to(poll))
+        .route("/mark_read", web::post().to(mark_read))
+        .route("/get_unread", web::post().to(get_unread))
+        .route("/get_templates", web::post().to(get_templates))
+        .route("/get_all_templates", web::post().to(get_all_templates))
+        .route("/new_template", web::post().to(new_template))
+        .route("/toggle_template", web::post().to(toggle_template))
+    );
+}
+
+fn participant(auth: &Auth) -> Option<Participant> {
+    ["admin", "curator", "restaurant", "courier"].iter()
+        .find(|role| auth.roles.contains(&role.to_string()))
+        .map(|role| Participant {
+            role: role.to_string(),
+            id: auth.id,
+        })
+}
+
+fn require_participant(auth: &Auth) -> Result<Participant> {
+    participant(auth).ok_or(ApiError {
+        code: 403,
+        message: "not permitted".to_string(),
+        error_type: ErrorType::InternalError,
+    })
+}
+
+pub async fn send(
+    auth: Auth,
+    form: web::Json<NewOrderMessage>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let author = require_participant(&auth)?;
+    let conn = conn.get()?;
+    let r = OrderMessage::send(&form, &author, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_thread(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let reader = require_participant(&auth)?;
+    let conn = conn.get()?;
+    let r = OrderMessage::get_thread(form.id, &reader, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+/// long poll: answers as soon as there are messages after `after_id`,
+/// or with an empty list once the timeout passes
+pub async fn poll(
+    auth: Auth,
+    form: web::Json<Poll>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let reader = require_participant(&auth)?;
+    let started = std::time::Instant::now();
+    loop {
+        let r = {
+            let conn = pool.get()?;
+            OrderMessage::get_after(form.order_id, form.after_id, &reader, &conn).await?
+        };
+        if !r.is_empty() || started.elapsed().as_secs() >= POLL_TIMEOUT_SECS {
+            return Ok(HttpResponse::Ok().json(r));
+        }
+        actix_web::rt::time::delay_for(std::time::Duration::from_millis(POLL_INTERVAL_MILLIS)).await;
+    }
+}
+
+pub async fn mark_read(
+    auth: Auth,
+    form: web::Json<Read>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let reader = require_participant(&auth)?;
+    let conn = conn.get()?;
+    let r = ReadMarker::mark(form.order_id, form.message_id, &reader, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_unread(
+    auth: Auth,
+    form: web::Json<OrderIds>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let reader = require_participant(&auth)?;
+    let conn = conn.get()?;
+    let r = ReadMarker::unread(&form.order_ids, &reader, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_templates(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let reader = require_participant(&auth)?;
+    let conn = conn.get()?;
+    let r = ChatTemplate::get_for_role(&reader.role, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_all_templates(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = ChatTemplate::get_all(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn new_template(
+    auth: Auth,
+    form: web::Json<NewChatTemplate>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = ChatTemplate::new(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn toggle_template(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = ChatTemplate::toggle(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
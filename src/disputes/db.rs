@@ -0,0 +1,307 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use crate::schema::{
+    disputes,
+    order_events,
+    orders,
+    sessions,
+};
+use crate::enum_types::*;
+use crate::ordering::db::Orders;
+use crate::ledger::db::{
+    LedgerTransaction,
+    Posting,
+};
+
+const MAX_ATTACHMENTS: usize = 10;
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct Dispute {
+    pub id: i64,
+    pub order_id: i64,
+    /// "courier" or "restaurant"
+    pub opened_by_role: String,
+    pub opened_by_id: i64,
+    pub category: DisputeCategory,
+    pub description: String,
+    /// links to photos and documents
+    pub attachments: Vec<String>,
+    pub status: DisputeStatus,
+    pub reviewer_role: Option<String>,
+    pub reviewer_id: Option<i64>,
+    /// order status before the fault was changed
+    pub previous_status: Option<OrderStatus>,
+    pub new_status: Option<OrderStatus>,
+    pub adjustment_id: Option<i64>,
+    pub resolution: Option<String>,
+    pub creation_datetime: chrono::NaiveDateTime,
+    pub resolve_datetime: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct NewDispute {
+    pub order_id: i64,
+    pub category: DisputeCategory,
+    pub description: String,
+    #[serde(default)]
+    pub attachments: Vec<String>,
+}
+
+/// `fault` moves the order to `FailureByCourier` or `FailureByRestaurant`,
+/// `adjustment` is booked on the courier's salary account; the payout booked
+/// at finalization is not recalculated for the new fault, the adjustment is
+/// the only correction of the courier's pay
+#[derive(Serialize,Deserialize,Clone)]
+pub struct DisputeResolution {
+    pub id: i64,
+    #[serde(default)]
+    pub fault: Option<OrderStatus>,
+    #[serde(default)]
+    pub adjustment: Option<i64>,
+    pub resolution: String,
+    #[serde(default)]
+    pub reject: bool,
+}
+
+/// the courier who carried the order
+async fn order_courier(
+    order: &Orders,
+    conn: &PgConnection,
+) -> Result<Option<i64>> {
+    let session_id = match order.session_id {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let r = sessions::table
+        .filter(sessions::id.eq(session_id))
+        .select(sessions::courier_id)
+        .get_result::<i64>(conn)
+        .optional()?;
+    Ok(r)
+}
+
+async fn log_event(
+    dispute: &Dispute,
+    kind: OrderEventKind,
+    status: Option<OrderStatus>,
+    courier_id: Option<i64>,
+    conn: &PgConnection,
+) -> Result<()> {
+    diesel::insert_into(order_events::table)
+        .values((
+            order_events::order_id.eq(dispute.order_id),
+            order_events::kind.eq(kind),
+            order_events::status.eq(status),
+            order_events::courier_id.eq(courier_id),
+            order_events::dispute_id.eq(dispute.id),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+impl Dispute {
+    /// true if the courier carried the order or the restaurant owns it
+    pub async fn is_party(
+        order: &Orders,
+        role: &str,
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<bool> {
+        match role {
+            "courier" => order.is_carried_by(id, conn).await,
+            "restaurant" => Ok(order.restaurant_id == Some(id)),
+            _ => Ok(false),
+        }
+    }
+
+    pub async fn open(
+        data: &NewDispute,
+        role: &str,
+        opened_by_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(!data.description.trim().is_empty(), "description is empty");
+        require!(data.attachments.len() <= MAX_ATTACHMENTS, "too many attachments");
+        require!(data.attachments.iter().all(|a| !a.trim().is_empty()), "empty attachment");
+        let order = Orders::get_by_id(data.order_id, conn).await?;
+        require!(Self::is_party(&order, role, opened_by_id, conn).await?, "not your order");
+        let open = disputes::table
+            .filter(disputes::order_id.eq(order.id))
+            .filter(disputes::opened_by_role.eq(role))
+            .filter(disputes::status.eq_any(vec![DisputeStatus::Open, DisputeStatus::InReview]))
+            .count()
+            .get_result::<i64>(conn)?;
+        require!(open == 0, "dispute on this order is already open");
+        let r = diesel::insert_into(disputes::table)
+            .values((
+                disputes::order_id.eq(order.id),
+                disputes::opened_by_role.eq(role),
+                disputes::opened_by_id.eq(opened_by_id),
+                disputes::category.eq(data.category),
+                disputes::description.eq(&data.description),
+                disputes::attachments.eq(&data.attachments),
+            ))
+            .get_result::<Self>(conn)?;
+        let courier_id = if role == "courier" { Some(opened_by_id) } else { None };
+        log_event(&r, OrderEventKind::DisputeOpened, None, courier_id, conn).await?;
+        Ok(r)
+    }
+
+    /// the curator takes the ticket
+    pub async fn review(
+        id: i64,
+        reviewer_role: &str,
+        reviewer_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let d = Self::get(id, conn).await?;
+        require!(d.status == DisputeStatus::Open, "dispute is already in review");
+        let r = diesel::update(disputes::table.filter(disputes::id.eq(d.id)))
+            .set((
+                disputes::status.eq(DisputeStatus::InReview),
+                disputes::reviewer_role.eq(reviewer_role),
+                disputes::reviewer_id.eq(reviewer_id),
+            ))
+            .get_result::<Self>(conn)?;
+        log_event(&r, OrderEventKind::DisputeInReview, None, None, conn).await?;
+        Ok(r)
+    }
+
+    /// closes the ticket, optionally moving the fault and correcting the courier's pay
+    pub async fn resolve(
+        data: &DisputeResolution,
+        reviewer_role: &str,
+        reviewer_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(!data.resolution.trim().is_empty(), "resolution is empty");
+        conn.transaction::<Self, ApiError, _>(|| futures::executor::block_on(async {
+            // a second reviewer resolving the same ticket waits here and then finds it closed
+            let d = disputes::table
+                .filter(disputes::id.eq(data.id))
+                .for_update()
+                .get_result::<Self>(conn)
+                .optional()?
+                .ok_or(ApiError {
+                    code: 404,
+                    message: "dispute not found".to_string(),
+                    error_type: ErrorType::InternalError,
+                })?;
+            require!(d.status == DisputeStatus::Open ||
+                d.status == DisputeStatus::InReview, "dispute is already closed");
+            require!(!data.reject || (data.fault.is_none() && data.adjustment.is_none()),
+                "rejected dispute can't change the order");
+            let order = Orders::get_by_id(d.order_id, conn).await?;
+            let courier_id = order_courier(&order, conn).await?;
+            let mut previous_status = None;
+            let mut new_status = None;
+            if let Some(fault) = &data.fault {
+                require!(*fault == OrderStatus::FailureByCourier ||
+                    *fault == OrderStatus::FailureByRestaurant, "fault is either courier's or restaurant's");
+                require!(order.finalize_datetime.is_some(), "order is not finalized");
+                require!(order.status != *fault, "order already has this fault");
+                diesel::update(orders::table.filter(orders::id.eq(order.id)))
+                    .set(orders::status.eq(fault.clone()))
+                    .execute(conn)?;
+                previous_status = Some(order.status.clone());
+                new_status = Some(fault.clone());
+                log_event(&d, OrderEventKind::FaultChanged, Some(fault.clone()), courier_id, conn).await?;
+            }
+            let mut adjustment_id = None;
+            if let Some(amount) = data.adjustment.filter(|a| *a != 0) {
+                let courier_id = courier_id.ok_or(ApiError {
+                    code: 400,
+                    message: "order has no courier".to_string(),
+                    error_type: ErrorType::InternalError,
+                })?;
+                let t = LedgerTransaction::post(&Posting {
+                    kind: LedgerEntryKind::Adjustment,
+                    courier_id,
+                    account: LedgerAccount::CourierSalary,
+                    amount,
+                    order_id: Some(order.id),
+                    actor_role: Some(reviewer_role.to_string()),
+                    actor_id: Some(reviewer_id),
+                    comment: Some(format!("dispute #{}", d.id)),
+                }, conn).await?;
+                adjustment_id = Some(t.id);
+                log_event(&d, OrderEventKind::DisputeAdjusted, None, Some(courier_id), conn).await?;
+            }
+            let (status, kind) = if data.reject {
+                (DisputeStatus::Rejected, OrderEventKind::DisputeRejected)
+            } else {
+                (DisputeStatus::Resolved, OrderEventKind::DisputeResolved)
+            };
+            let r = diesel::update(disputes::table.filter(disputes::id.eq(d.id)))
+                .set((
+                    disputes::status.eq(status),
+                    disputes::reviewer_role.eq(reviewer_role),
+                    disputes::reviewer_id.eq(reviewer_id),
+                    disputes::previous_status.eq(previous_status),
+                    disputes::new_status.eq(new_status),
+                    disputes::adjustment_id.eq(adjustment_id),
+                    disputes::resolution.eq(&data.resolution),
+                    disputes::resolve_datetime.eq(diesel::dsl::now),
+                ))
+                .get_result::<Self>(conn)?;
+            log_event(&r, kind, None, None, conn).await?;
+            Ok(r)
+        }))
+    }
+
+    pub async fn get(
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = disputes::table
+            .filter(disputes::id.eq(id))
+            .get_result::<Self>(conn)
+            .optional()?;
+        r.ok_or(ApiError {
+            code: 404,
+            message: "dispute not found".to_string(),
+            error_type: ErrorType::InternalError,
+        })
+    }
+
+    pub async fn get_by_order(
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = disputes::table
+            .filter(disputes::order_id.eq(order_id))
+            .order(disputes::creation_datetime)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_opened_by(
+        role: &str,
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = disputes::table
+            .filter(disputes::opened_by_role.eq(role))
+            .filter(disputes::opened_by_id.eq(id))
+            .order(disputes::creation_datetime.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_unresolved(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = disputes::table
+            .filter(disputes::status.eq_any(vec![DisputeStatus::Open, DisputeStatus::InReview]))
+            .order(disputes::creation_datetime)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
@@ -0,0 +1,155 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use crate::ordering::db::Orders;
+use super::db::{
+    Dispute,
+    NewDispute,
+    DisputeResolution,
+};
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+pub fn disputes_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/disputes")
+        .route("/open", web::post().to(open))
+        .route("/review", web::post().to(review))
+        .route("/resolve", web::post().to(resolve))
+        .route("/get", web::post().to(get))
+        .route("/get_by_order", web::post().to(get_by_order))
+        .route("/get_mine", web::post().to(get_mine))
+        .route("/get_unresolved", web::post().to(get_unresolved))
+    );
+}
+
+fn is_staff(auth: &Auth) -> bool {
+    auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string())
+}
+
+fn staff_role(auth: &Auth) -> &'static str {
+    if auth.roles.contains(&"admin".to_string()) { "admin" } else { "curator" }
+}
+
+/// the role a courier or a restaurant opens disputes with
+fn party_role(auth: &Auth) -> Option<&'static str> {
+    if auth.roles.contains(&"courier".to_string()) {
+        Some("courier")
+    } else if auth.roles.contains(&"restaurant".to_string()) {
+        Some("restaurant")
+    } else {
+        None
+    }
+}
+
+async fn can_see(
+    auth: &Auth,
+    order_id: i64,
+    conn: &PgConnection,
+) -> Result<bool> {
+    if is_staff(auth) {
+        return Ok(true);
+    }
+    let role = match party_role(auth) {
+        Some(role) => role,
+        None => return Ok(false),
+    };
+    let order = Orders::get_by_id(order_id, conn).await?;
+    Dispute::is_party(&order, role, auth.id, conn).await
+}
+
+pub async fn open(
+    auth: Auth,
+    form: web::Json<NewDispute>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let role = party_role(&auth);
+    require!(role.is_some(),"not permitted"); 
+    let conn = conn.get()?;
+    let r = Dispute::open(&form, role.unwrap_or_default(), auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn review(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = Dispute::review(form.id, staff_role(&auth), auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn resolve(
+    auth: Auth,
+    form: web::Json<DisputeResolution>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = Dispute::resolve(&form, staff_role(&auth), auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let r = Dispute::get(form.id, &conn).await?;
+    require!(can_see(&auth, r.order_id, &conn).await?,"not permitted"); 
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_by_order(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    require!(can_see(&auth, form.id, &conn).await?,"not permitted"); 
+    let r = Dispute::get_by_order(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_mine(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let role = party_role(&auth);
+    require!(role.is_some(),"not permitted"); 
+    let conn = conn.get()?;
+    let r = Dispute::get_opened_by(role.unwrap_or_default(), auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_unresolved(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = Dispute::get_unresolved(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
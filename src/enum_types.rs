@@ -8,7 +8,7 @@ pub enum PayMethod {
     AlreadyPayed,
 }
 
-#[derive(Debug,Clone,DbEnum,Serialize,Deserialize)]
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq)]
 #[DieselType = "Orderstatus"]
 #[DbValueStyle ="PascalCase"]
 pub enum OrderStatus {
@@ -21,9 +21,11 @@ pub enum OrderStatus {
     FailureByCourier,
     FailureByRestaurant,
     Success,
+    Returning,
+    Returned,
 }
 
-#[derive(Debug,Clone,DbEnum,Serialize,Deserialize)]
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
 #[DieselType = "Transporttype"]
 #[DbValueStyle ="PascalCase"]
 pub enum TransportType {
@@ -31,3 +33,125 @@ pub enum TransportType {
     Feet,
     Bicycle,
 }
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Commissionkind"]
+#[DbValueStyle ="PascalCase"]
+pub enum CommissionKind {
+    Fixed,
+    Percentage,
+    Tiered,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Ledgeraccount"]
+#[DbValueStyle ="PascalCase"]
+pub enum LedgerAccount {
+    CourierSalary,
+    CourierCash,
+    CourierTerminal,
+    Company,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Ledgerentrykind"]
+#[DbValueStyle ="PascalCase"]
+pub enum LedgerEntryKind {
+    Earning,
+    CashCollected,
+    TerminalCollected,
+    Payout,
+    Adjustment,
+    Penalty,
+    Handover,
+    Tip,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Handoverstatus"]
+#[DbValueStyle ="PascalCase"]
+pub enum HandoverStatus {
+    Declared,
+    Confirmed,
+    Disputed,
+    Resolved,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Sizeclass"]
+#[DbValueStyle ="PascalCase"]
+pub enum SizeClass {
+    Small,
+    Medium,
+    Large,
+    Oversized,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Returnreason"]
+#[DbValueStyle ="PascalCase"]
+pub enum ReturnReason {
+    NoAnswer,
+    Refused,
+    WrongAddress,
+    Other,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Ordereventkind"]
+#[DbValueStyle ="PascalCase"]
+pub enum OrderEventKind {
+    StatusChanged,
+    CourierArrived,
+    DisputeOpened,
+    DisputeInReview,
+    FaultChanged,
+    DisputeAdjusted,
+    DisputeResolved,
+    DisputeRejected,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Disputecategory"]
+#[DbValueStyle ="PascalCase"]
+pub enum DisputeCategory {
+    Late,
+    Damaged,
+    Missing,
+    WrongOrder,
+    Payment,
+    Behaviour,
+    Other,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Disputestatus"]
+#[DbValueStyle ="PascalCase"]
+pub enum DisputeStatus {
+    Open,
+    InReview,
+    Resolved,
+    Rejected,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Shiftbookingstatus"]
+#[DbValueStyle ="PascalCase"]
+pub enum ShiftBookingStatus {
+    Booked,
+    Cancelled,
+    Attended,
+    NoShow,
+}
+
+#[derive(Debug,Clone,DbEnum,Serialize,Deserialize,PartialEq,Copy)]
+#[DieselType = "Availabilitystatus"]
+#[DbValueStyle ="PascalCase"]
+pub enum AvailabilityStatus {
+    Offline,
+    OnlineIdle,
+    Offered,
+    Busy,
+    Paused,
+    StaleLocation,
+}
@@ -0,0 +1,333 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use r2d2_redis::redis::{self, Commands};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{
+    Bigint,
+    Time,
+    Double,
+    Bool,
+    Timestamp,
+    Nullable,
+};
+use crate::enum_types::*;
+use crate::temp::db::{
+    Coords,
+    get_courier_coords,
+};
+
+/// how long averaged speeds stay cached in redis, seconds
+const SPEED_CACHE_TTL: usize = 3600;
+/// how long a stored eta is served before it is estimated again, seconds;
+/// couriers sending their location refresh it sooner
+const ETA_CACHE_TTL: usize = 300;
+/// completed orders older than this don't count towards average speeds
+const SPEED_HISTORY_DAYS: i32 = 30;
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct Route {
+    /// metres
+    pub distance: f64,
+    /// seconds
+    pub duration: f64,
+}
+
+/// rough straight-line speeds in m/s for when there is no delivery history
+/// and the road route isn't worth asking for
+fn nominal_speed(transport: &TransportType) -> f64 {
+    match transport {
+        TransportType::Car => 8.0,
+        TransportType::Bicycle => 4.0,
+        TransportType::Feet => 1.4,
+    }
+}
+
+fn osrm_profile(transport: &TransportType) -> &'static str {
+    match transport {
+        TransportType::Car => "car",
+        TransportType::Bicycle => "bike",
+        TransportType::Feet => "foot",
+    }
+}
+
+pub async fn route(
+    from: &Coords,
+    to: &Coords,
+    transport: &TransportType,
+) -> Result<Route> {
+    use serde_json::Value;
+    let url = format!(
+        "http://router.project-osrm.org/route/v1/{}/{},{};{},{}",
+        osrm_profile(transport), from.lng, from.lat, to.lng, to.lat);
+    let resp: Value = reqwest::get(&url)
+        .await?
+        .json()
+        .await?;
+    let err = || ApiError {
+        code: 500,
+        message: "error in getting route from json".to_string(),
+        error_type: ErrorType::InternalError,
+    };
+    Ok(Route {
+        distance: resp["routes"][0]["distance"].as_f64().ok_or_else(err)?,
+        duration: resp["routes"][0]["duration"].as_f64().ok_or_else(err)?,
+    })
+}
+
+#[derive(QueryableByName)]
+struct AverageSpeed {
+    #[sql_type="Nullable<Double>"]
+    speed: Option<f64>,
+}
+
+/// average delivering speed in m/s over recently completed orders,
+/// measured along the straight line between the restaurant and the client
+pub async fn average_speed(
+    transport: &TransportType,
+    conn: &PgConnection,
+    conn_redis: &mut redis::Connection,
+) -> Result<Option<f64>> {
+    let key = format!("avg_speed:{}", osrm_profile(transport));
+    let cached: Option<f64> = conn_redis.get(&key)?;
+    if cached.is_some() {
+        return Ok(cached);
+    }
+    let r = diesel::sql_query("SELECT AVG(
+            (2 * 6371000 * ASIN(SQRT(POWER(SIN(RADIANS((o.address_lat - r.location_lat) / 2)), 2) +
+            COS(RADIANS(r.location_lat)) * COS(RADIANS(o.address_lat)) *
+            POWER(SIN(RADIANS((o.address_lng - r.location_lng) / 2)), 2))))
+            / EXTRACT(EPOCH FROM (o.delivery_datetime - o.take_datetime))) as speed
+        FROM orders o
+            JOIN restaurants r ON r.id = o.restaurant_id
+            JOIN sessions s ON s.id = o.session_id
+        WHERE o.status = 'Success' AND s.transport = $1
+            AND o.delivery_datetime > o.take_datetime
+            AND o.finalize_datetime > CURRENT_TIMESTAMP - ($2 || ' days')::interval;")
+        .bind::<Transporttype,_>(transport)
+        .bind::<diesel::sql_types::Integer,_>(SPEED_HISTORY_DAYS)
+        .get_result::<AverageSpeed>(conn)?;
+    if let Some(speed) = r.speed {
+        conn_redis.set_ex(&key, speed, SPEED_CACHE_TTL)?;
+    }
+    Ok(r.speed)
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct EtaOrder {
+    #[sql_type="Bigint"]
+    pub order_id: i64,
+    #[sql_type="Orderstatus"]
+    pub status: OrderStatus,
+    #[sql_type="Double"]
+    pub restaurant_lat: f64,
+    #[sql_type="Double"]
+    pub restaurant_lng: f64,
+    #[sql_type="Double"]
+    pub destination_lat: f64,
+    #[sql_type="Double"]
+    pub destination_lng: f64,
+    #[sql_type="Time"]
+    pub cooking_time: chrono::NaiveTime,
+    #[sql_type="Timestamp"]
+    pub creation_datetime: chrono::NaiveDateTime,
+    #[sql_type="Bool"]
+    pub is_big_order: bool,
+    #[sql_type="Nullable<Bigint>"]
+    pub courier_id: Option<i64>,
+    #[sql_type="Nullable<Transporttype>"]
+    pub transport: Option<TransportType>,
+}
+
+const ETA_ORDER_QUERY: &str = "SELECT
+        o.id as order_id,
+        o.status,
+        r.location_lat as restaurant_lat,
+        r.location_lng as restaurant_lng,
+        o.address_lat as destination_lat,
+        o.address_lng as destination_lng,
+        o.cooking_time,
+        o.creation_datetime,
+        o.is_big_order,
+        s.courier_id,
+        s.transport
+    FROM orders o
+        JOIN restaurants r ON r.id = o.restaurant_id
+        LEFT JOIN sessions s ON s.id = o.session_id";
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct Eta {
+    pub order_id: i64,
+    /// none once the courier has picked the order up
+    pub pickup_eta: Option<chrono::NaiveDateTime>,
+    pub delivery_eta: Option<chrono::NaiveDateTime>,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl EtaOrder {
+    pub async fn by_id(
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::sql_query(format!("{} WHERE o.id = $1;", ETA_ORDER_QUERY))
+            .bind::<Bigint,_>(order_id)
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn active_by_courier(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = diesel::sql_query(format!("{} WHERE s.courier_id = $1 AND s.ended_at IS NULL
+            AND o.status = ANY('{{CourierConfirmation,Cooking,ReadyForDelivery,Delivering}}');",
+            ETA_ORDER_QUERY))
+            .bind::<Bigint,_>(courier_id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    fn ready_at(&self) -> chrono::NaiveDateTime {
+        use chrono::Timelike;
+        self.creation_datetime + chrono::Duration::seconds(
+            self.cooking_time.num_seconds_from_midnight() as i64)
+    }
+
+    fn transport(&self) -> TransportType {
+        match &self.transport {
+            Some(t) => t.clone(),
+            None if self.is_big_order => TransportType::Car,
+            None => TransportType::Feet,
+        }
+    }
+
+    /// without a speed history the road route is asked for only when
+    /// `by_road` is set, otherwise the nominal speed of the transport is used
+    async fn travel(
+        &self,
+        from: &Coords,
+        to: &Coords,
+        by_road: bool,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<chrono::Duration> {
+        let transport = self.transport();
+        // the average speed is a straight-line one, so it is applied to
+        // the straight-line distance rather than to the road route
+        let secs = match average_speed(&transport, conn, conn_redis).await? {
+            Some(speed) if speed > 0.0 => from.distance_to(to) / speed,
+            _ if by_road => route(from, to, &transport).await?.duration,
+            _ => from.distance_to(to) / nominal_speed(&transport),
+        };
+        Ok(chrono::Duration::seconds(secs as i64))
+    }
+
+    /// times are counted from the database clock, the one `creation_datetime`
+    /// and so `ready_at` come from
+    pub async fn estimate(
+        &self,
+        by_road: bool,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Eta> {
+        let now = diesel::select(diesel::dsl::now)
+            .get_result::<chrono::NaiveDateTime>(conn)?;
+        let restaurant = Coords { lat: self.restaurant_lat, lng: self.restaurant_lng };
+        let destination = Coords { lat: self.destination_lat, lng: self.destination_lng };
+        let courier = match self.courier_id {
+            Some(id) => get_courier_coords(id, conn_redis)
+                .await?
+                .map(|c| Coords { lat: c.lat, lng: c.lng }),
+            None => None,
+        };
+        let (pickup_eta, delivery_eta) = match self.status {
+            OrderStatus::Delivering => {
+                let delivery = match courier {
+                    Some(c) => Some(now + self.travel(&c, &destination, by_road, conn, conn_redis).await?),
+                    None => None,
+                };
+                (None, delivery)
+            },
+            OrderStatus::CourierFinding |
+            OrderStatus::CourierConfirmation |
+            OrderStatus::Cooking |
+            OrderStatus::ReadyForDelivery => {
+                let arrival = match courier {
+                    Some(c) => now + self.travel(&c, &restaurant, by_road, conn, conn_redis).await?,
+                    None => now,
+                };
+                let pickup = std::cmp::max(arrival, self.ready_at());
+                let delivery = pickup + self.travel(&restaurant, &destination, by_road, conn, conn_redis).await?;
+                (Some(pickup), Some(delivery))
+            },
+            _ => (None, None),
+        };
+        Ok(Eta {
+            order_id: self.order_id,
+            pickup_eta,
+            delivery_eta,
+            updated_at: now,
+        })
+    }
+}
+
+fn eta_key(order_id: i64) -> String {
+    format!("eta:{}", order_id)
+}
+
+impl Eta {
+    pub async fn get(
+        order_id: i64,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Self> {
+        let cached: Option<String> = conn_redis.get(eta_key(order_id))?;
+        if let Some(eta) = cached.and_then(|v| serde_json::from_str(&v).ok()) {
+            return Ok(eta);
+        }
+        let order = EtaOrder::by_id(order_id, conn).await?;
+        Self::store(order.estimate(true, conn, conn_redis).await?, conn_redis).await
+    }
+
+    async fn store(
+        eta: Self,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Self> {
+        if eta.delivery_eta.is_some() {
+            conn_redis.set_ex(eta_key(eta.order_id), serde_json::to_string(&eta)
+                .expect("err serialize"), ETA_CACHE_TTL)?;
+        } else {
+            conn_redis.del(eta_key(eta.order_id))?;
+        }
+        Ok(eta)
+    }
+
+    /// drops the stored eta so the next read estimates it for the new status
+    pub async fn invalidate(
+        order_id: i64,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<()> {
+        conn_redis.del(eta_key(order_id))?;
+        Ok(())
+    }
+
+    /// recalculates etas of every order the courier is carrying,
+    /// called on each location update, so the road route is not asked for
+    pub async fn refresh_for_courier(
+        courier_id: i64,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Vec<Self>> {
+        let orders = EtaOrder::active_by_courier(courier_id, conn).await?;
+        let mut r = Vec::with_capacity(orders.len());
+        for order in orders {
+            let eta = order.estimate(false, conn, conn_redis).await?;
+            r.push(Self::store(eta, conn_redis).await?);
+        }
+        Ok(r)
+    }
+}
@@ -0,0 +1,56 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use r2d2_redis::{RedisConnectionManager, r2d2, redis::{self, Commands}};
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type RedisDbPool = r2d2::Pool<RedisConnectionManager>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use crate::ordering::db::Orders;
+use super::db::Eta;
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+pub fn eta_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/eta")
+        .route("/get", web::post().to(get_eta))
+    );
+}
+
+pub async fn get_eta(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let mut redis_conn = redis_conn.get()?;
+    if auth.roles.contains(&"restaurant".to_string()) {
+        let order = Orders::get_by_id(form.id, &conn).await?;
+        require!(order.restaurant_id == Some(auth.id), "not your order");
+    } else if auth.roles.contains(&"courier".to_string()) {
+        let order = Orders::get_by_id(form.id, &conn).await?;
+        require!(order.is_carried_by(auth.id, &conn).await?, "not your order");
+    } else {
+        require!(auth.roles.contains(&"curator".to_string()) ||
+            auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    }
+    let r = Eta::get(form.id, &conn, &mut redis_conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
@@ -0,0 +1,360 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::sql_types::*;
+
+use crate::schema::{
+    cash_handovers,
+    cash_limits,
+    sessions,
+};
+use crate::enum_types::*;
+use crate::ledger::db::{
+    Balance,
+    LedgerTransaction,
+    Posting,
+};
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct CashHandover {
+    pub id: i64,
+    pub courier_id: i64,
+    pub session_id: Option<i64>,
+    pub declared_amount: i64,
+    pub received_amount: Option<i64>,
+    /// declared minus received, set once the curator confirms
+    pub discrepancy: Option<i64>,
+    pub status: HandoverStatus,
+    /// curator or admin who counted the cash
+    pub actor_role: Option<String>,
+    pub actor_id: Option<i64>,
+    pub comment: Option<String>,
+    pub creation_datetime: chrono::NaiveDateTime,
+    pub confirm_datetime: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct CashLimit {
+    pub id: i64,
+    pub courier_tier: Option<i16>,
+    pub courier_id: Option<i64>,
+    pub amount: i64,
+    /// none for the seeded default
+    pub admin_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+/// limit without tier and courier is the global one
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="cash_limits"]
+pub struct NewCashLimit {
+    pub courier_tier: Option<i16>,
+    pub courier_id: Option<i64>,
+    pub amount: i64,
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct CashStatus {
+    #[sql_type="Bigint"]
+    pub courier_id: i64,
+    #[sql_type="Varchar"]
+    pub courier_name: String,
+    #[sql_type="Varchar"]
+    pub courier_surname: String,
+    #[sql_type="Varchar"]
+    pub courier_phone: String,
+    #[sql_type="Bigint"]
+    pub cash: i64,
+    #[sql_type="Nullable<Bigint>"]
+    pub cash_limit: Option<i64>,
+    #[sql_type="Bool"]
+    pub exceeded: bool,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Declaration {
+    pub amount: i64,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Confirmation {
+    pub id: i64,
+    pub received_amount: i64,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Resolution {
+    pub id: i64,
+    pub comment: String,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Period {
+    pub from: chrono::NaiveDate,
+    pub till: chrono::NaiveDate,
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct ShiftCashReport {
+    #[sql_type="Bigint"]
+    pub session_id: i64,
+    #[sql_type="Bigint"]
+    pub courier_id: i64,
+    #[sql_type="Varchar"]
+    pub courier_name: String,
+    #[sql_type="Varchar"]
+    pub courier_surname: String,
+    #[sql_type="Date"]
+    pub session_day: chrono::NaiveDate,
+    #[sql_type="Bigint"]
+    pub cash_orders: i64,
+    #[sql_type="Bigint"]
+    pub cash_collected: i64,
+    #[sql_type="Bigint"]
+    pub handed_over: i64,
+    #[sql_type="Bigint"]
+    pub cash_balance: i64,
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct CashReconciliation {
+    #[sql_type="Bigint"]
+    pub courier_id: i64,
+    #[sql_type="Varchar"]
+    pub courier_name: String,
+    #[sql_type="Varchar"]
+    pub courier_surname: String,
+    /// cash orders finalized as `Success` that day
+    #[sql_type="Bigint"]
+    pub orders_cash: i64,
+    /// cash booked on the courier's ledger that day
+    #[sql_type="Bigint"]
+    pub ledger_cash: i64,
+    #[sql_type="Bigint"]
+    pub handed_over: i64,
+    #[sql_type="Bigint"]
+    pub difference: i64,
+}
+
+impl CashHandover {
+    pub async fn declare(
+        courier_id: i64,
+        data: &Declaration,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(data.amount > 0, "amount must be positive");
+        let balance = Balance::get(courier_id, conn).await?;
+        require!(data.amount <= balance.cash, "declared more cash than you hold");
+        let pending = cash_handovers::table
+            .filter(cash_handovers::courier_id.eq(courier_id))
+            .filter(cash_handovers::status.eq(HandoverStatus::Declared))
+            .count()
+            .get_result::<i64>(conn)?;
+        require!(pending == 0, "previous handover is not confirmed yet");
+        let session_id = sessions::table
+            .filter(sessions::courier_id.eq(courier_id))
+            .filter(sessions::ended_at.is_null())
+            .select(sessions::id)
+            .first::<i64>(conn)
+            .optional()?;
+        let r = diesel::insert_into(cash_handovers::table)
+            .values((
+                cash_handovers::courier_id.eq(courier_id),
+                cash_handovers::session_id.eq(session_id),
+                cash_handovers::declared_amount.eq(data.amount),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// records what the curator actually received and takes it off the courier's cash account
+    pub async fn confirm(
+        data: &Confirmation,
+        actor_id: i64,
+        actor_role: &str,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(data.received_amount >= 0, "amount can't be negative");
+        let h = Self::get(data.id, conn).await?;
+        let discrepancy = h.declared_amount - data.received_amount;
+        let status = if discrepancy == 0 {
+            HandoverStatus::Confirmed
+        } else {
+            HandoverStatus::Disputed
+        };
+        conn.transaction::<Self, ApiError, _>(|| futures::executor::block_on(async {
+            // only the first of concurrent confirmations finds the handover declared
+            let r = diesel::update(cash_handovers::table
+                    .filter(cash_handovers::id.eq(h.id))
+                    .filter(cash_handovers::status.eq(HandoverStatus::Declared)))
+                .set((
+                    cash_handovers::received_amount.eq(data.received_amount),
+                    cash_handovers::discrepancy.eq(discrepancy),
+                    cash_handovers::status.eq(status),
+                    cash_handovers::actor_role.eq(actor_role),
+                    cash_handovers::actor_id.eq(actor_id),
+                    cash_handovers::comment.eq(&data.comment),
+                    cash_handovers::confirm_datetime.eq(diesel::dsl::now),
+                ))
+                .get_result::<Self>(conn)
+                .optional()?
+                .ok_or(ApiError {
+                    code: 400,
+                    message: "handover is already confirmed".to_string(),
+                    error_type: ErrorType::InternalError,
+                })?;
+            if data.received_amount > 0 {
+                LedgerTransaction::post(&Posting {
+                    kind: LedgerEntryKind::Handover,
+                    courier_id: h.courier_id,
+                    account: LedgerAccount::CourierCash,
+                    amount: -data.received_amount,
+                    order_id: None,
+                    actor_role: Some(actor_role.to_string()),
+                    actor_id: Some(actor_id),
+                    comment: Some(format!("cash handover #{}", h.id)),
+                }, conn).await?;
+            }
+            Ok(r)
+        }))
+    }
+
+    /// closes an escalated discrepancy, money corrections go through ledger adjustments
+    pub async fn resolve(
+        data: &Resolution,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let h = Self::get(data.id, conn).await?;
+        require!(h.status == HandoverStatus::Disputed, "handover is not disputed");
+        let r = diesel::update(cash_handovers::table.filter(cash_handovers::id.eq(h.id)))
+            .set((
+                cash_handovers::status.eq(HandoverStatus::Resolved),
+                cash_handovers::comment.eq(&data.comment),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get(
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = cash_handovers::table
+            .filter(cash_handovers::id.eq(id))
+            .get_result::<Self>(conn)
+            .optional()?;
+        r.ok_or(ApiError {
+            code: 404,
+            message: "handover not found".to_string(),
+            error_type: ErrorType::InternalError,
+        })
+    }
+
+    pub async fn get_by_courier(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = cash_handovers::table
+            .filter(cash_handovers::courier_id.eq(courier_id))
+            .order(cash_handovers::creation_datetime.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_pending(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = cash_handovers::table
+            .filter(cash_handovers::status.eq(HandoverStatus::Declared))
+            .order(cash_handovers::creation_datetime)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_disputed(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = cash_handovers::table
+            .filter(cash_handovers::status.eq(HandoverStatus::Disputed))
+            .order(cash_handovers::creation_datetime)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl ShiftCashReport {
+    pub async fn get(
+        data: &Period,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = diesel::sql_query("select * from shift_cash_report
+            WHERE session_day BETWEEN $1 AND $2 ORDER BY session_day, courier_id;")
+            .bind::<Date,_>(data.from)
+            .bind::<Date,_>(data.till)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl CashReconciliation {
+    pub async fn get(
+        day: chrono::NaiveDate,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = diesel::sql_query("select * from cash_reconciliation($1);")
+            .bind::<Date,_>(day)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl CashLimit {
+    pub async fn new(
+        data: &NewCashLimit,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(data.amount >= 0, "limit can't be negative");
+        require!(data.courier_tier.is_none() || data.courier_id.is_none(),
+            "limit is either per tier or per courier");
+        let r = diesel::insert_into(cash_limits::table)
+            .values((data, cash_limits::admin_id.eq(admin_id)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = cash_limits::table
+            .order(cash_limits::creation_datetime.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl CashStatus {
+    pub async fn get(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::sql_query("select * from courier_cash_status WHERE courier_id=$1;")
+            .bind::<Bigint,_>(courier_id)
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_exceeded(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = diesel::sql_query("select * from courier_cash_status WHERE exceeded ORDER BY cash DESC;")
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
@@ -0,0 +1,197 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use super::db::{
+    CashHandover,
+    Declaration,
+    Confirmation,
+    Resolution,
+    Period,
+    ShiftCashReport,
+    CashReconciliation,
+    CashLimit,
+    NewCashLimit,
+    CashStatus,
+};
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct Day {
+    day: chrono::NaiveDate,
+}
+
+pub fn handovers_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/handovers")
+        .route("/declare", web::post().to(declare))
+        .route("/confirm", web::post().to(confirm))
+        .route("/resolve", web::post().to(resolve))
+        .route("/get_by_courier", web::post().to(get_by_courier))
+        .route("/get_pending", web::post().to(get_pending))
+        .route("/get_disputed", web::post().to(get_disputed))
+        .route("/shift_report", web::post().to(shift_report))
+        .route("/reconciliation", web::post().to(reconciliation))
+        .route("/new_limit", web::post().to(new_limit))
+        .route("/get_limits", web::post().to(get_limits))
+        .route("/get_cash_status", web::post().to(get_cash_status))
+        .route("/get_over_limit", web::post().to(get_over_limit))
+    );
+}
+
+pub async fn declare(
+    auth: Auth,
+    form: web::Json<Declaration>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CashHandover::declare(auth.id, &form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn confirm(
+    auth: Auth,
+    form: web::Json<Confirmation>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let role = if auth.roles.contains(&"admin".to_string()) { "admin" } else { "curator" };
+    let r = CashHandover::confirm(&form, auth.id, role, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn resolve(
+    auth: Auth,
+    form: web::Json<Resolution>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = CashHandover::resolve(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_by_courier(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()) ||
+        (auth.roles.contains(&"courier".to_string()) && auth.id == form.id),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CashHandover::get_by_courier(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_pending(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CashHandover::get_pending(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_disputed(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = CashHandover::get_disputed(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn shift_report(
+    auth: Auth,
+    form: web::Json<Period>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = ShiftCashReport::get(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn reconciliation(
+    auth: Auth,
+    form: web::Json<Day>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CashReconciliation::get(form.day, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn new_limit(
+    auth: Auth,
+    form: web::Json<NewCashLimit>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = CashLimit::new(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_limits(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CashLimit::get_all(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_cash_status(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()) ||
+        (auth.roles.contains(&"courier".to_string()) && auth.id == form.id),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CashStatus::get(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_over_limit(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CashStatus::get_exceeded(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
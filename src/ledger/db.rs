@@ -0,0 +1,294 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use crate::schema::{
+    ledger_transactions,
+    ledger_entries,
+    couriers,
+};
+use crate::enum_types::*;
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct LedgerTransaction {
+    pub id: i64,
+    pub kind: LedgerEntryKind,
+    pub courier_id: i64,
+    pub order_id: Option<i64>,
+    pub actor_role: Option<String>,
+    pub actor_id: Option<i64>,
+    pub comment: Option<String>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct LedgerEntry {
+    pub id: i64,
+    pub transaction_id: i64,
+    pub account: LedgerAccount,
+    pub courier_id: i64,
+    pub amount: i64,
+}
+
+/// one movement of money on a courier account, mirrored on the company account
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Posting {
+    pub kind: LedgerEntryKind,
+    pub courier_id: i64,
+    pub account: LedgerAccount,
+    pub amount: i64,
+    #[serde(default)]
+    pub order_id: Option<i64>,
+    #[serde(default)]
+    pub actor_role: Option<String>,
+    #[serde(default)]
+    pub actor_id: Option<i64>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize,Deserialize,Clone,Default,Debug)]
+pub struct Balance {
+    /// owed to the courier
+    pub salary: i64,
+    /// cash the courier holds
+    pub cash: i64,
+    /// collected through the courier's terminal
+    pub term: i64,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct StatementLine {
+    pub transaction: LedgerTransaction,
+    pub account: LedgerAccount,
+    pub amount: i64,
+    pub balance: Balance,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Statement {
+    pub courier_id: i64,
+    pub from: chrono::NaiveDateTime,
+    pub till: chrono::NaiveDateTime,
+    pub opening: Balance,
+    pub lines: Vec<StatementLine>,
+    pub closing: Balance,
+}
+
+impl Balance {
+    fn add(&mut self, account: LedgerAccount, amount: i64) {
+        match account {
+            LedgerAccount::CourierSalary => self.salary += amount,
+            LedgerAccount::CourierCash => self.cash += amount,
+            LedgerAccount::CourierTerminal => self.term += amount,
+            LedgerAccount::Company => (),
+        }
+    }
+
+    async fn sum(
+        courier_id: i64,
+        before: Option<chrono::NaiveDateTime>,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let mut query = ledger_entries::table
+            .inner_join(ledger_transactions::table)
+            .filter(ledger_entries::courier_id.eq(courier_id))
+            .filter(ledger_entries::account.ne(LedgerAccount::Company))
+            .select((ledger_entries::account, ledger_entries::amount))
+            .into_boxed();
+        if let Some(before) = before {
+            query = query.filter(ledger_transactions::creation_datetime.lt(before));
+        }
+        let mut r = Self::default();
+        for (account, amount) in query.get_results::<(LedgerAccount, i64)>(conn)? {
+            r.add(account, amount);
+        }
+        Ok(r)
+    }
+
+    pub async fn get(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        Self::sum(courier_id, None, conn).await
+    }
+}
+
+impl LedgerTransaction {
+    pub async fn post(
+        data: &Posting,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(data.account != LedgerAccount::Company, "company account is posted automatically");
+        require!(data.amount != 0, "empty posting");
+        let r = conn.transaction::<Self, diesel::result::Error, _>(|| {
+            let t = diesel::insert_into(ledger_transactions::table)
+                .values((
+                    ledger_transactions::kind.eq(data.kind),
+                    ledger_transactions::courier_id.eq(data.courier_id),
+                    ledger_transactions::order_id.eq(data.order_id),
+                    ledger_transactions::actor_role.eq(&data.actor_role),
+                    ledger_transactions::actor_id.eq(data.actor_id),
+                    ledger_transactions::comment.eq(&data.comment),
+                ))
+                .get_result::<Self>(conn)?;
+            diesel::insert_into(ledger_entries::table)
+                .values(&vec![
+                    (
+                        ledger_entries::transaction_id.eq(t.id),
+                        ledger_entries::account.eq(data.account),
+                        ledger_entries::courier_id.eq(data.courier_id),
+                        ledger_entries::amount.eq(data.amount),
+                    ),
+                    (
+                        ledger_entries::transaction_id.eq(t.id),
+                        ledger_entries::account.eq(LedgerAccount::Company),
+                        ledger_entries::courier_id.eq(data.courier_id),
+                        ledger_entries::amount.eq(-data.amount),
+                    ),
+                ])
+                .execute(conn)?;
+            Ok(t)
+        })?;
+        Ok(r)
+    }
+
+    /// books a finalized order: the courier payout, the tip and the money collected from the client
+    pub async fn post_order(
+        order_id: i64,
+        courier_id: i64,
+        status: &OrderStatus,
+        method: PayMethod,
+        courier_payout: i64,
+        order_price: i64,
+        tip: i64,
+        conn: &PgConnection,
+    ) -> Result<()> {
+        let posting = |kind, account, amount| Posting {
+            kind,
+            courier_id,
+            account,
+            amount,
+            order_id: Some(order_id),
+            actor_role: None,
+            actor_id: None,
+            comment: None,
+        };
+        match status {
+            OrderStatus::Success => {
+                if courier_payout != 0 {
+                    Self::post(&posting(LedgerEntryKind::Earning,
+                        LedgerAccount::CourierSalary, courier_payout), conn).await?;
+                }
+                if tip != 0 {
+                    Self::post(&posting(LedgerEntryKind::Tip,
+                        LedgerAccount::CourierSalary, tip), conn).await?;
+                }
+                match method {
+                    PayMethod::Cash => {
+                        Self::post(&posting(LedgerEntryKind::CashCollected,
+                            LedgerAccount::CourierCash, order_price), conn).await?;
+                    },
+                    PayMethod::Card => {
+                        Self::post(&posting(LedgerEntryKind::TerminalCollected,
+                            LedgerAccount::CourierTerminal, order_price), conn).await?;
+                    },
+                    PayMethod::AlreadyPayed => (),
+                }
+            },
+            OrderStatus::FailureByRestaurant |
+            OrderStatus::Returned if courier_payout != 0 => {
+                Self::post(&posting(LedgerEntryKind::Earning,
+                    LedgerAccount::CourierSalary, courier_payout), conn).await?;
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// zeroes the requested balances with recorded payouts
+    pub async fn settle(
+        courier_id: i64,
+        accounts: &[LedgerAccount],
+        actor_role: &str,
+        actor_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        conn.transaction::<Vec<Self>, ApiError, _>(|| futures::executor::block_on(async {
+            // a concurrent settlement waits here and then reads the zeroed balance
+            couriers::table
+                .filter(couriers::id.eq(courier_id))
+                .select(couriers::id)
+                .for_update()
+                .get_result::<i64>(conn)?;
+            let balance = Balance::get(courier_id, conn).await?;
+            let mut r = vec![];
+            for account in accounts {
+                let amount = match account {
+                    LedgerAccount::CourierSalary => balance.salary,
+                    LedgerAccount::CourierCash => balance.cash,
+                    LedgerAccount::CourierTerminal => balance.term,
+                    LedgerAccount::Company => 0,
+                };
+                if amount == 0 {
+                    continue;
+                }
+                r.push(Self::post(&Posting {
+                    kind: LedgerEntryKind::Payout,
+                    courier_id,
+                    account: *account,
+                    amount: -amount,
+                    order_id: None,
+                    actor_role: Some(actor_role.to_string()),
+                    actor_id: Some(actor_id),
+                    comment: Some("settlement".to_string()),
+                }, conn).await?);
+            }
+            Ok(r)
+        }))
+    }
+
+    pub async fn statement(
+        courier_id: i64,
+        from: chrono::NaiveDateTime,
+        till: chrono::NaiveDateTime,
+        conn: &PgConnection,
+    ) -> Result<Statement> {
+        require!(from < till, "empty period");
+        let opening = Balance::sum(courier_id, Some(from), conn).await?;
+        let rows = ledger_entries::table
+            .inner_join(ledger_transactions::table)
+            .filter(ledger_entries::courier_id.eq(courier_id))
+            .filter(ledger_entries::account.ne(LedgerAccount::Company))
+            .filter(ledger_transactions::creation_datetime.ge(from))
+            .filter(ledger_transactions::creation_datetime.lt(till))
+            .order(ledger_transactions::id)
+            .select((ledger_transactions::all_columns, ledger_entries::account, ledger_entries::amount))
+            .get_results::<(Self, LedgerAccount, i64)>(conn)?;
+        let mut balance = opening.clone();
+        let lines = rows.into_iter()
+            .map(|(transaction, account, amount)| {
+                balance.add(account, amount);
+                StatementLine {
+                    transaction,
+                    account,
+                    amount,
+                    balance: balance.clone(),
+                }
+            })
+            .collect();
+        Ok(Statement {
+            courier_id,
+            from,
+            till,
+            opening,
+            lines,
+            closing: balance,
+        })
+    }
+}
@@ -0,0 +1,94 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use crate::enum_types::LedgerEntryKind;
+use super::db::{
+    Balance,
+    LedgerTransaction,
+    Posting,
+};
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct Period {
+    courier_id: i64,
+    from: chrono::NaiveDateTime,
+    till: chrono::NaiveDateTime,
+}
+
+pub fn ledger_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/ledger")
+        .route("/balance", web::post().to(get_balance))
+        .route("/statement", web::post().to(get_statement))
+        .route("/adjust", web::post().to(adjust))
+    );
+}
+
+fn is_staff(auth: &Auth) -> bool {
+    auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string())
+}
+
+pub async fn get_balance(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth) || (auth.roles.contains(&"courier".to_string()) && auth.id == form.id),
+        "not permitted");
+    let conn = conn.get()?;
+    let r = Balance::get(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_statement(
+    auth: Auth,
+    form: web::Json<Period>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth) || (auth.roles.contains(&"courier".to_string()) && auth.id == form.courier_id),
+        "not permitted");
+    let conn = conn.get()?;
+    let r = LedgerTransaction::statement(form.courier_id, form.from, form.till, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn adjust(
+    auth: Auth,
+    form: web::Json<Posting>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth), "not permitted");
+    require!(form.kind == LedgerEntryKind::Adjustment ||
+        form.kind == LedgerEntryKind::Penalty, "only adjustments and penalties");
+    let conn = conn.get()?;
+    let mut form = form.into_inner();
+    form.actor_role = Some(if auth.roles.contains(&"admin".to_string()) {
+        "admin" } else { "curator" }.to_string());
+    form.actor_id = Some(auth.id);
+    if form.kind == LedgerEntryKind::Penalty {
+        form.amount = -form.amount.abs();
+    }
+    let r = LedgerTransaction::post(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
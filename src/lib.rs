@@ -12,3 +12,16 @@ pub mod enum_types;
 pub mod form;
 pub mod temp;
 pub mod ordering;
+pub mod tracking;
+pub mod eta;
+pub mod tariffs;
+pub mod payouts;
+pub mod ledger;
+pub mod handovers;
+pub mod ratings;
+pub mod disputes;
+pub mod chat;
+pub mod zones;
+pub mod shifts;
+pub mod breaks;
+pub mod availability;
@@ -13,6 +13,19 @@ use topgo::users::routes::users_routes;
 use topgo::form::create_landing_form;
 use topgo::temp::routes::location_routes;
 use topgo::ordering::routes::ordering_routes;
+use topgo::tracking::routes::tracking_routes;
+use topgo::eta::routes::eta_routes;
+use topgo::tariffs::routes::tariffs_routes;
+use topgo::payouts::routes::payouts_routes;
+use topgo::ledger::routes::ledger_routes;
+use topgo::handovers::routes::handovers_routes;
+use topgo::ratings::routes::ratings_routes;
+use topgo::disputes::routes::disputes_routes;
+use topgo::chat::routes::chat_routes;
+use topgo::zones::routes::zones_routes;
+use topgo::shifts::routes::shifts_routes;
+use topgo::breaks::routes::breaks_routes;
+use topgo::availability::routes::availability_routes;
 use r2d2_redis::{r2d2 as rd_redis, redis, RedisConnectionManager};
 use actix_cors::Cors;
 
@@ -54,6 +67,19 @@ async fn main() -> std::io::Result<()> {
                 .configure(users_routes)
                 .configure(location_routes)
                 .configure(ordering_routes)
+                .configure(tracking_routes)
+                .configure(eta_routes)
+                .configure(tariffs_routes)
+                .configure(payouts_routes)
+                .configure(ledger_routes)
+                .configure(handovers_routes)
+                .configure(ratings_routes)
+                .configure(disputes_routes)
+                .configure(chat_routes)
+                .configure(zones_routes)
+                .configure(shifts_routes)
+                .configure(breaks_routes)
+                .configure(availability_routes)
                 .route("/form", web::post().to(create_landing_form))
             )
     })
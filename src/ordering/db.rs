@@ -12,9 +12,16 @@ use super::*;
 
 use crate::schema::{
     orders,
+    order_payouts,
+    order_items,
+    phone_access_log,
+    order_returns,
+    order_events,
     couriers_approvals,
     courier_rating,
+    delivery_zones,
     notifications,
+    restaurants,
     sessions,
 };
 use diesel::sql_types::{
@@ -26,24 +33,70 @@ use diesel::sql_types::{
     Bool,
 };
 use crate::enum_types::*;
+use crate::tariffs::db::{
+    Tariff,
+    TariffInput,
+    Quote,
+};
+use crate::eta::db::route;
+use crate::temp::db::Coords;
+use crate::users::db::Restaurants;
+use crate::payouts::db::OrderPayout;
+use crate::temp::db::{
+    get_courier_coords,
+    geo_position,
+    nearest_couriers,
+    NearbyCourier,
+    NearbyFilter,
+};
+use crate::ledger::db::{
+    LedgerTransaction,
+    Posting,
+};
+use crate::handovers::db::CashStatus;
+use crate::zones::db::DeliveryZone;
+use crate::shifts::db::ShiftBooking;
 
+/// courier and restaurant are taken from the order, not from the request
 #[derive(Serialize,Deserialize,Clone,Insertable)]
 #[table_name="courier_rating"]
 pub struct CourierRating {
+    #[serde(skip_deserializing)]
     pub courier_id: i64,
     pub order_id: i64,
     pub look: i16,
     pub politeness: i16,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(skip_deserializing)]
+    pub restaurant_id: i64,
 }
 
 impl CourierRating {
     pub async fn new (
-        data: &Self, 
+        data: &mut Self, 
+        restaurant_id: i64,
         conn: &PgConnection,
     ) -> Result<()> {
-        diesel::insert_into(courier_rating::table)
-            .values(data)
+        require!((1..=5).contains(&data.look) && (1..=5).contains(&data.politeness),
+            "rating must be from 1 to 5");
+        let order = Orders::get_by_id(data.order_id, conn).await?;
+        require!(order.restaurant_id == Some(restaurant_id), "not your order");
+        require!(order.status == OrderStatus::Delivered ||
+            order.status == OrderStatus::Success, "order is not delivered");
+        let courier_id = sessions::table
+            .filter(sessions::id.nullable().eq(order.session_id))
+            .select(sessions::courier_id)
+            .get_result::<i64>(conn)?;
+        data.courier_id = courier_id;
+        data.restaurant_id = restaurant_id;
+        // order_id is unique, a second rating of the order inserts nothing
+        let inserted = diesel::insert_into(courier_rating::table)
+            .values(&*data)
+            .on_conflict(courier_rating::order_id)
+            .do_nothing()
             .execute(conn)?;
+        require!(inserted > 0, "order is already rated");
         Ok(())
     }
 }
@@ -138,6 +191,15 @@ pub struct Orders {
     pub take_datetime: Option<chrono::NaiveDateTime>,
     pub delivery_datetime: Option<chrono::NaiveDateTime>,
     pub creation_datetime: chrono::NaiveDateTime,
+    pub tracking_token: Option<String>,
+    pub delivery_price: i64,
+    pub tariff_version: Option<i64>,
+    pub tip: i64,
+    pub zone_id: Option<i64>,
+    /// set on the parcels of a split order
+    pub parent_id: Option<i64>,
+    /// the order was split into parcels and is not dispatched itself
+    pub is_split: bool,
 }
 
 #[derive(Serialize,Deserialize,Clone)]
@@ -152,51 +214,718 @@ pub struct Finalization {
 #[table_name="orders"]
 pub struct NewOrder {
     pub restaurant_id: i64,
+    #[serde(default)]
     pub details: String,
+    #[serde(default)]
     pub is_big_order: bool,
     pub delivery_address: String,
     pub address_lat: f64,
     pub address_lng: f64,
     pub method: PayMethod,
+    #[serde(skip_deserializing)]
     pub courier_share: i64,
     pub order_price: i64,
     pub cooking_time: chrono::NaiveTime, 
     pub client_phone: String,
     pub client_comment: String,
+    #[serde(skip_deserializing)]
+    pub delivery_price: i64,
+    #[serde(skip_deserializing)]
+    pub tariff_version: Option<i64>,
+    #[serde(default)]
+    pub tip: i64,
+    #[serde(skip_deserializing)]
+    pub zone_id: Option<i64>,
+    #[serde(skip_deserializing)]
+    pub parent_id: Option<i64>,
+}
+
+/// the order with its line items, `details` alone is still accepted
+#[derive(Serialize,Deserialize,Clone)]
+pub struct NewOrderRequest {
+    #[serde(flatten)]
+    pub order: NewOrder,
+    #[serde(default)]
+    pub items: Vec<OrderItemRequest>,
+}
+
+/// either a catalog reference or a fully described item
+#[derive(Serialize,Deserialize,Clone)]
+pub struct OrderItemRequest {
+    pub catalog_item_id: Option<i64>,
+    pub quantity: i32,
+    pub name: Option<String>,
+    pub unit_price: Option<i64>,
+    pub weight: Option<i32>,
+    pub size_class: Option<SizeClass>,
+    #[serde(default)]
+    pub is_fragile: bool,
+    #[serde(default)]
+    pub is_drink: bool,
+    #[serde(default)]
+    pub is_hot: bool,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="order_items"]
+pub struct NewOrderItem {
+    pub catalog_item_id: Option<i64>,
+    pub name: String,
+    pub quantity: i32,
+    pub unit_price: i64,
+    /// grams
+    pub weight: Option<i32>,
+    pub size_class: SizeClass,
+    #[serde(default)]
+    pub is_fragile: bool,
+    #[serde(default)]
+    pub is_drink: bool,
+    #[serde(default)]
+    pub is_hot: bool,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct OrderItem {
+    pub id: i64,
+    pub order_id: i64,
+    pub catalog_item_id: Option<i64>,
+    pub name: String,
+    pub quantity: i32,
+    pub unit_price: i64,
+    pub weight: Option<i32>,
+    pub size_class: SizeClass,
+    pub is_fragile: bool,
+    pub is_drink: bool,
+    pub is_hot: bool,
+}
+
+/// total weight in grams above which an order needs a car
+const BIG_ORDER_WEIGHT: i64 = 10_000;
+
+impl NewOrderItem {
+    pub fn validate(&self) -> Result<()> {
+        require!(!self.name.is_empty(), "item name is empty");
+        require!(self.quantity > 0, "item quantity must be positive");
+        require!(self.unit_price >= 0, "item price can't be negative");
+        require!(self.weight.map(|w| w >= 0).unwrap_or(true), "item weight can't be negative");
+        Ok(())
+    }
+}
+
+impl OrderItem {
+    pub async fn get_by_order(
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = order_items::table
+            .filter(order_items::order_id.eq(order_id))
+            .order(order_items::id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+/// keeps the first two and the last two characters
+pub fn mask_phone(phone: &str) -> String {
+    let chars = phone.chars().collect::<Vec<_>>();
+    if chars.len() <= 4 {
+        return "*".repeat(chars.len());
+    }
+    chars.iter()
+        .enumerate()
+        .map(|(i, c)| if i < 2 || i >= chars.len() - 2 { *c } else { '*' })
+        .collect()
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct PhoneAccess {
+    pub id: i64,
+    pub order_id: i64,
+    pub actor_role: String,
+    pub actor_id: i64,
+    pub reason: Option<String>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Unmask {
+    pub order_id: i64,
+    pub reason: String,
+}
+
+impl PhoneAccess {
+    async fn log(
+        order_id: i64,
+        actor_role: &str,
+        actor_id: i64,
+        reason: Option<&str>,
+        conn: &PgConnection,
+    ) -> Result<()> {
+        diesel::insert_into(phone_access_log::table)
+            .values((
+                phone_access_log::order_id.eq(order_id),
+                phone_access_log::actor_role.eq(actor_role),
+                phone_access_log::actor_id.eq(actor_id),
+                phone_access_log::reason.eq(reason),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// the assigned courier calls the client of an active order
+    pub async fn call_client(
+        order_id: i64,
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<String> {
+        let order = Orders::get_by_id(order_id, conn).await?;
+        require!(order.is_active(), "order is not active");
+        require!(order.is_carried_by(courier_id, conn).await?, "not your order");
+        Self::log(order.id, "courier", courier_id, None, conn).await?;
+        Ok(order.client_phone)
+    }
+
+    pub async fn unmask(
+        data: &Unmask,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<String> {
+        require!(!data.reason.is_empty(), "reason is required");
+        let order = Orders::get_by_id(data.order_id, conn).await?;
+        Self::log(order.id, "admin", admin_id, Some(&data.reason), conn).await?;
+        Ok(order.client_phone)
+    }
+
+    pub async fn get_by_order(
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = phone_access_log::table
+            .filter(phone_access_log::order_id.eq(order_id))
+            .order(phone_access_log::creation_datetime.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+/// status changes are written by a trigger, arrivals by the courier,
+/// dispute steps by the disputes module
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct OrderEvent {
+    pub id: i64,
+    pub order_id: i64,
+    pub kind: OrderEventKind,
+    pub status: Option<OrderStatus>,
+    pub courier_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+    pub dispute_id: Option<i64>,
+}
+
+impl OrderEvent {
+    /// the courier is at the restaurant and waits for the order
+    pub async fn courier_arrived(
+        order_id: i64,
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let order = Orders::get_by_id(order_id, conn).await?;
+        require!(order.is_carried_by(courier_id, conn).await?, "not your order");
+        require!(order.status == OrderStatus::Cooking ||
+            order.status == OrderStatus::ReadyForDelivery, "order is already picked up");
+        let r = diesel::insert_into(order_events::table)
+            .values((
+                order_events::order_id.eq(order.id),
+                order_events::kind.eq(OrderEventKind::CourierArrived),
+                order_events::courier_id.eq(courier_id),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_by_order(
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = order_events::table
+            .filter(order_events::order_id.eq(order_id))
+            .order(order_events::creation_datetime)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct OrderReturn {
+    pub id: i64,
+    pub order_id: i64,
+    pub courier_id: i64,
+    pub reason: ReturnReason,
+    pub comment: Option<String>,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub report_datetime: chrono::NaiveDateTime,
+    pub confirm_datetime: Option<chrono::NaiveDateTime>,
+    pub restaurant_comment: Option<String>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct ReturnReport {
+    pub order_id: i64,
+    pub reason: ReturnReason,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct ReturnConfirmation {
+    pub order_id: i64,
+    pub comment: Option<String>,
+}
+
+impl OrderReturn {
+    /// the courier failed to hand the order over and brings it back,
+    /// the attempt is stamped with the last known courier location
+    pub async fn report(
+        data: &ReturnReport,
+        courier_id: i64,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Self> {
+        let order = Orders::get_by_id(data.order_id, conn).await?;
+        require!(order.status == OrderStatus::Delivering, "order is not being delivered");
+        require!(order.is_carried_by(courier_id, conn).await?, "not your order");
+        let coords = get_courier_coords(courier_id, conn_redis).await?;
+        let r = conn.transaction::<Self, ApiError, _>(|| {
+            // the status guard keeps a repeated report from opening a second return
+            let updated = diesel::update(orders::table
+                    .filter(orders::id.eq(order.id))
+                    .filter(orders::status.eq(OrderStatus::Delivering)))
+                .set(orders::status.eq(OrderStatus::Returning))
+                .execute(conn)?;
+            require!(updated > 0, "order is not being delivered");
+            let r = diesel::insert_into(order_returns::table)
+                .values((
+                    order_returns::order_id.eq(order.id),
+                    order_returns::courier_id.eq(courier_id),
+                    order_returns::reason.eq(data.reason),
+                    order_returns::comment.eq(&data.comment),
+                    order_returns::lat.eq(coords.as_ref().map(|c| c.lat)),
+                    order_returns::lng.eq(coords.as_ref().map(|c| c.lng)),
+                ))
+                .get_result::<Self>(conn)?;
+            Ok(r)
+        })?;
+        Ok(r)
+    }
+
+    /// the restaurant got the order back, the courier is paid by the return rule
+    pub async fn confirm(
+        data: &ReturnConfirmation,
+        restaurant_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let order = Orders::get_by_id(data.order_id, conn).await?;
+        require!(order.restaurant_id == Some(restaurant_id), "not your order");
+        require!(order.status == OrderStatus::Returning, "order is not returning");
+        let ret = order_returns::table
+            .filter(order_returns::order_id.eq(order.id))
+            .filter(order_returns::confirm_datetime.is_null())
+            .get_result::<Self>(conn)?;
+        let r = conn.transaction::<Self, ApiError, _>(|| futures::executor::block_on(async {
+            // the status guard keeps a repeated confirmation from paying twice
+            let updated = diesel::update(orders::table
+                    .filter(orders::id.eq(order.id))
+                    .filter(orders::status.eq(OrderStatus::Returning)))
+                .set((
+                    orders::status.eq(OrderStatus::Returned),
+                    orders::finalize_datetime.eq(diesel::dsl::now),
+                    orders::finalize_comment.eq(&data.comment),
+                ))
+                .execute(conn)?;
+            require!(updated > 0, "order is not returning");
+            diesel::update(crate::schema::couriers::table
+                    .filter(crate::schema::couriers::id.eq(ret.courier_id)))
+                .set(crate::schema::couriers::is_in_order.eq(false))
+                .execute(conn)?;
+            let r = diesel::update(order_returns::table.filter(order_returns::id.eq(ret.id)))
+                .set((
+                    order_returns::confirm_datetime.eq(diesel::dsl::now),
+                    order_returns::restaurant_comment.eq(&data.comment),
+                ))
+                .get_result::<Self>(conn)?;
+            let payout = OrderPayout::finalize_return(order.id, ret.courier_id, order.method, conn).await?;
+            LedgerTransaction::post_order(
+                order.id,
+                ret.courier_id,
+                &OrderStatus::Returned,
+                order.method,
+                payout.courier_payout.unwrap_or(0),
+                order.order_price,
+                0,
+                conn,
+            ).await?;
+            Ok(r)
+        }))?;
+        Ok(r)
+    }
+
+    pub async fn get_by_order(
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = order_returns::table
+            .filter(order_returns::order_id.eq(order_id))
+            .order(order_returns::report_datetime.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Parcel {
+    pub details: String,
+    /// the part of the order price collected with this parcel
+    pub order_price: i64,
+    #[serde(default)]
+    pub is_big_order: bool,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Split {
+    pub order_id: i64,
+    pub parcels: Vec<Parcel>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct SplitOrder {
+    pub order: Orders,
+    pub parcels: Vec<Orders>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Tip {
+    pub order_id: i64,
+    pub tip: i64,
+}
+
+impl NewOrder {
+    /// checks the price against the items and derives
+    /// `is_big_order` and `details` from them
+    pub fn apply_items(
+        &mut self,
+        items: &[NewOrderItem],
+    ) -> Result<()> {
+        if items.is_empty() {
+            require!(!self.details.is_empty(), "order has neither items nor details");
+            return Ok(());
+        }
+        for item in items {
+            item.validate()?;
+        }
+        let overflow = || ApiError {
+            code: 400,
+            message: "item totals are too large".to_string(),
+            error_type: ErrorType::InternalError,
+        };
+        let total = items.iter()
+            .try_fold(0i64, |sum, i| i.unit_price.checked_mul(i.quantity as i64)
+                .and_then(|t| sum.checked_add(t)))
+            .ok_or_else(overflow)?;
+        require!(total == self.order_price, "order price doesn't match the items");
+        let weight = items.iter()
+            .try_fold(0i64, |sum, i| (i.weight.unwrap_or(0) as i64).checked_mul(i.quantity as i64)
+                .and_then(|w| sum.checked_add(w)))
+            .ok_or_else(overflow)?;
+        self.is_big_order = weight > BIG_ORDER_WEIGHT || items.iter().any(|i| {
+            i.size_class == SizeClass::Large || i.size_class == SizeClass::Oversized
+        });
+        if self.details.is_empty() {
+            self.details = items.iter()
+                .map(|i| format!("{} x{}", i.name, i.quantity))
+                .collect::<Vec<_>>()
+                .join(", ");
+        }
+        Ok(())
+    }
+
+    /// prices the delivery by the current tariff
+    /// from the restaurant to the delivery address,
+    /// rejects addresses outside the restaurant's delivery zones;
+    /// only big orders know their transport up front, the courier share
+    /// of the rest is priced again once a courier takes them
+    pub async fn quote(
+        &self,
+        conn: &PgConnection,
+    ) -> Result<(Quote, Option<i64>)> {
+        let restaurant = Restaurants::from_id(self.restaurant_id, conn).await?;
+        let from = Coords { lat: restaurant.location_lat, lng: restaurant.location_lng };
+        let to = Coords { lat: self.address_lat, lng: self.address_lng };
+        let route = route(&from, &to, &TransportType::Car).await?;
+        let zone = DeliveryZone::locate(self.restaurant_id, &to, route.distance, conn).await?;
+        let tariff = Tariff::current(conn).await?;
+        let quote = tariff.quote(&TariffInput {
+            distance: route.distance,
+            transport: if self.is_big_order { Some(TransportType::Car) } else { None },
+            is_big_order: self.is_big_order,
+            datetime: chrono::Utc::now().naive_utc(),
+            zone_multiplier: zone.as_ref().map(|z| z.tariff_multiplier).unwrap_or(1.0),
+        });
+        Ok((quote, zone.map(|z| z.id)))
+    }
 }
 
 impl Orders {
+    /// the courier is on the way and may need to reach the client
+    pub fn is_active(&self) -> bool {
+        match self.status {
+            OrderStatus::CourierConfirmation |
+            OrderStatus::Cooking |
+            OrderStatus::ReadyForDelivery |
+            OrderStatus::Delivering => true,
+            _ => false,
+        }
+    }
+
+    pub fn masked(mut self) -> Self {
+        self.client_phone = mask_phone(&self.client_phone);
+        self
+    }
+
+    /// orders are looked for around the courier's point in the radius index,
+    /// the one nearest-courier searches see, the sent point only stands in
+    /// while the courier is not on the map
     pub async fn get_suggested(
         data: &OrderRequest, 
         conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
     ) -> Result<Vec<SuggestedOrders>> {
+        let point = geo_position(data.courier_id, conn_redis).await?
+            .unwrap_or(Coords { lat: data.lat, lng: data.lng });
         let r = diesel::sql_query("select * from find_suitable_orders($1,$2,$3);")
-            .bind::<Double,_>(data.lat)
-            .bind::<Double,_>(data.lng)
+            .bind::<Double,_>(point.lat)
+            .bind::<Double,_>(point.lng)
             .bind::<Bigint,_>(data.courier_id)
             .get_results::<SuggestedOrders>(conn)?;
         Ok(r)
     }
     pub async fn create_order (
         data: &mut NewOrder, 
+        items: &[NewOrderItem],
         conn: &PgConnection,
-    ) -> Result<()> {
-        if data.method == PayMethod::Card {
-            data.courier_share = data.courier_share * 104 / 100;
+    ) -> Result<Self> {
+        use rand::{Rng, distributions::Alphanumeric};
+        require!(data.tip >= 0, "tip can't be negative");
+        data.apply_items(items)?;
+        let (quote, zone_id) = data.quote(conn).await?;
+        data.zone_id = zone_id;
+        data.delivery_price = quote.delivery_price;
+        data.courier_share = quote.courier_share;
+        data.tariff_version = Some(quote.tariff_version);
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        // the helpers below only query the database, so blocking on them
+        // inside the transaction doesn't hold the runtime up
+        let r = conn.transaction::<Self, ApiError, _>(|| futures::executor::block_on(async {
+            let order = diesel::insert_into(orders::table)
+                .values((data.clone(), orders::tracking_token.eq(&token)))
+                .get_result::<Self>(conn)?;
+            if !items.is_empty() {
+                diesel::insert_into(order_items::table)
+                    .values(&items.iter()
+                        .map(|i| (i, order_items::order_id.eq(order.id)))
+                        .collect::<Vec<_>>())
+                    .execute(conn)?;
+            }
+            let payout = OrderPayout::open(order.id, quote.courier_share, data.method, conn).await?;
+            let r = diesel::update(orders::table.filter(orders::id.eq(order.id)))
+                .set(orders::courier_share.eq(payout.base_share + payout.surcharge))
+                .get_result::<Self>(conn)?;
+            Ok(r)
+        }))?;
+        Ok(r)
+    }
+
+    /// turns an undispatched order into parcels, each dispatched as an order of its own;
+    /// the parent keeps the totals and follows the parcels' statuses
+    pub async fn split (
+        data: &Split,
+        conn: &PgConnection,
+    ) -> Result<SplitOrder> {
+        use rand::{Rng, distributions::Alphanumeric};
+        require!(data.parcels.len() >= 2, "at least two parcels are needed");
+        require!(data.parcels.iter().all(|p| p.order_price >= 0), "parcel price can't be negative");
+        require!(data.parcels.iter().all(|p| !p.details.is_empty()), "parcel details are empty");
+        let order = Self::get_by_id(data.order_id, conn).await?;
+        require!(order.parent_id.is_none() && !order.is_split, "order is already split");
+        require!(order.status == OrderStatus::CourierFinding && order.session_id.is_none(),
+            "order is already dispatched");
+        let restaurant_id = order.restaurant_id.ok_or(ApiError {
+            code: 400,
+            message: "order has no restaurant".to_string(),
+            error_type: ErrorType::InternalError,
+        })?;
+        require!(data.parcels.iter().map(|p| p.order_price).sum::<i64>() == order.order_price,
+            "parcel prices don't add up to the order price");
+        let mut parcels = vec![];
+        for p in data.parcels.iter() {
+            let mut parcel = NewOrder {
+                restaurant_id,
+                details: p.details.clone(),
+                is_big_order: p.is_big_order,
+                delivery_address: order.delivery_address.clone(),
+                address_lat: order.address_lat,
+                address_lng: order.address_lng,
+                method: order.method,
+                courier_share: 0,
+                order_price: p.order_price,
+                cooking_time: order.cooking_time,
+                client_phone: order.client_phone.clone(),
+                client_comment: order.client_comment.clone(),
+                delivery_price: 0,
+                tariff_version: None,
+                tip: 0,
+                zone_id: order.zone_id,
+                parent_id: Some(order.id),
+            };
+            let (quote, _) = parcel.quote(conn).await?;
+            parcel.delivery_price = quote.delivery_price;
+            parcel.courier_share = quote.courier_share;
+            parcel.tariff_version = Some(quote.tariff_version);
+            parcels.push((parcel, quote.courier_share));
         }
-        diesel::insert_into(orders::table)
-            .values(data.clone())
-            .execute(conn)?;
-        Ok(())
+        let tokens = parcels.iter()
+            .map(|_| rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect::<String>())
+            .collect::<Vec<_>>();
+        conn.transaction::<SplitOrder, ApiError, _>(|| futures::executor::block_on(async {
+            // the parcels were priced without the lock, the checks are repeated under it
+            // so a courier taking the order or a second split in between is refused
+            let locked = orders::table
+                .filter(orders::id.eq(order.id))
+                .for_update()
+                .get_result::<Self>(conn)?;
+            require!(locked.parent_id.is_none() && !locked.is_split, "order is already split");
+            require!(locked.status == OrderStatus::CourierFinding && locked.session_id.is_none(),
+                "order is already dispatched");
+            require!(locked.order_price == order.order_price, "order price has changed");
+            diesel::delete(order_payouts::table.filter(order_payouts::order_id.eq(order.id)))
+                .execute(conn)?;
+            let mut r = vec![];
+            for (i, ((parcel, share), token)) in parcels.iter().zip(tokens.iter()).enumerate() {
+                // the parent is never posted to the ledger, its tip goes with the first parcel
+                let mut parcel = parcel.clone();
+                if i == 0 {
+                    parcel.tip = locked.tip;
+                }
+                let created = diesel::insert_into(orders::table)
+                    .values((parcel, orders::tracking_token.eq(token)))
+                    .get_result::<Self>(conn)?;
+                let payout = OrderPayout::open(created.id, *share, order.method, conn).await?;
+                let created = diesel::update(orders::table.filter(orders::id.eq(created.id)))
+                    .set(orders::courier_share.eq(payout.base_share + payout.surcharge))
+                    .get_result::<Self>(conn)?;
+                r.push(created);
+            }
+            let order = diesel::update(orders::table.filter(orders::id.eq(order.id)))
+                .set((
+                    orders::is_split.eq(true),
+                    orders::tip.eq(0),
+                    orders::delivery_price.eq(r.iter().map(|p| p.delivery_price).sum::<i64>()),
+                    orders::courier_share.eq(r.iter().map(|p| p.courier_share).sum::<i64>()),
+                ))
+                .get_result::<Self>(conn)?;
+            Ok(SplitOrder {
+                order,
+                parcels: r,
+            })
+        }))
+    }
+
+    pub async fn get_parcels (
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = orders::table
+            .filter(orders::parent_id.eq(order_id))
+            .order(orders::id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_by_id (
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = orders::table
+            .filter(orders::id.eq(id))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// idle couriers nearest to the restaurant that can carry a waiting order
+    pub async fn nearest_couriers (
+        order_id: i64,
+        count: usize,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Vec<NearbyCourier>> {
+        let order = Self::get_by_id(order_id, conn).await?;
+        require!(order.status == OrderStatus::CourierFinding, "order is not waiting for a courier");
+        let restaurant_id = order.restaurant_id.ok_or(ApiError {
+            code: 400,
+            message: "order has no restaurant".to_string(),
+            error_type: ErrorType::InternalError,
+        })?;
+        let (lat, lng) = restaurants::table
+            .filter(restaurants::id.eq(restaurant_id))
+            .select((restaurants::location_lat, restaurants::location_lng))
+            .get_result::<(f64, f64)>(conn)?;
+        let filter = NearbyFilter {
+            statuses: vec![AvailabilityStatus::OnlineIdle],
+            transport: if order.is_big_order { Some(vec![TransportType::Car]) } else { None },
+        };
+        nearest_couriers(&Coords { lat, lng }, count, &filter, conn, conn_redis).await
+    }
+
+    /// true if the order is assigned to one of the courier's sessions
+    pub async fn is_carried_by (
+        &self,
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<bool> {
+        let session_id = match self.session_id {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        let r = sessions::table
+            .filter(sessions::id.eq(session_id))
+            .filter(sessions::courier_id.eq(courier_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        Ok(r > 0)
     }
 
     pub async fn get_orders_by_session_id (
         session_id: i64,
+        courier_id: i64,
         conn: &PgConnection,
     ) -> Result<Vec<Self>> {
         let r = orders::table
             .filter(orders::session_id.eq(session_id))
             .get_results::<Self>(conn)?;
+        let own = sessions::table
+            .filter(sessions::id.eq(session_id))
+            .filter(sessions::courier_id.eq(courier_id))
+            .count()
+            .get_result::<i64>(conn)? > 0;
+        let r = r.into_iter()
+            .map(|o| if own && o.is_active() { o } else { o.masked() })
+            .collect();
         Ok(r)
     }
 
@@ -211,7 +940,10 @@ impl Orders {
             .get_results::<i64>(conn)?;
         let r = orders::table
             .filter(orders::id.eq_any(r))
-            .get_results::<Self>(conn)?;
+            .get_results::<Self>(conn)?
+            .into_iter()
+            .map(|o| if o.is_active() { o } else { o.masked() })
+            .collect();
         Ok(r)
     }
     
@@ -221,19 +953,86 @@ impl Orders {
     ) -> Result<Vec<Self>> {
         let r = orders::table
             .filter(orders::restaurant_id.eq(id))
-            .get_results::<Self>(conn)?;
+            .get_results::<Self>(conn)?
+            .into_iter()
+            .map(Self::masked)
+            .collect();
         Ok(r)
     }
     
+    /// the courier share by the current tariff for the transport
+    /// the order is carried by, the client's price stays as quoted
+    async fn quote_share(
+        &self,
+        transport: &TransportType,
+        conn: &PgConnection,
+    ) -> Result<Quote> {
+        let restaurant_id = self.restaurant_id.ok_or(ApiError {
+            code: 400,
+            message: "order has no restaurant".to_string(),
+            error_type: ErrorType::InternalError,
+        })?;
+        let restaurant = Restaurants::from_id(restaurant_id, conn).await?;
+        let from = Coords { lat: restaurant.location_lat, lng: restaurant.location_lng };
+        let to = Coords { lat: self.address_lat, lng: self.address_lng };
+        let route = route(&from, &to, transport).await?;
+        let zone_multiplier = match self.zone_id {
+            Some(id) => delivery_zones::table
+                .filter(delivery_zones::id.eq(id))
+                .select(delivery_zones::tariff_multiplier)
+                .get_result::<f64>(conn)
+                .optional()?
+                .unwrap_or(1.0),
+            None => 1.0,
+        };
+        let tariff = Tariff::current(conn).await?;
+        Ok(tariff.quote(&TariffInput {
+            distance: route.distance,
+            transport: Some(*transport),
+            is_big_order: self.is_big_order,
+            datetime: chrono::Utc::now().naive_utc(),
+            zone_multiplier,
+        }))
+    }
+
+    /// the courier share is priced again for the transport of the session
     pub async fn take_order (
         order_id: i64,
         courier_id: i64,
         conn: &PgConnection,
     ) -> Result<()> {
-        diesel::sql_query("select * from take_order($1,$2);")
-            .bind::<Bigint,_>(order_id)
-            .bind::<Bigint,_>(courier_id)
-            .execute(conn)?;
+        let order = Self::get_by_id(order_id, conn).await?;
+        require!(!order.is_split, "split orders are taken by parcels");
+        if order.method == PayMethod::Cash {
+            let status = CashStatus::get(courier_id, conn).await?;
+            require!(!status.exceeded, "cash limit exceeded, hand over cash first");
+        }
+        let transport = sessions::table
+            .filter(sessions::courier_id.eq(courier_id))
+            .filter(sessions::ended_at.is_null())
+            .select(sessions::transport)
+            .first::<TransportType>(conn)
+            .optional()?
+            .ok_or(ApiError {
+                code: 400,
+                message: "no open session".to_string(),
+                error_type: ErrorType::InternalError,
+            })?;
+        let quote = order.quote_share(&transport, conn).await?;
+        conn.transaction::<(), ApiError, _>(|| futures::executor::block_on(async {
+            diesel::sql_query("select * from take_order($1,$2);")
+                .bind::<Bigint,_>(order_id)
+                .bind::<Bigint,_>(courier_id)
+                .execute(conn)?;
+            diesel::update(orders::table.filter(orders::id.eq(order_id)))
+                .set((
+                    orders::courier_share.eq(quote.courier_share),
+                    orders::tariff_version.eq(quote.tariff_version),
+                ))
+                .execute(conn)?;
+            OrderPayout::reprice(order_id, quote.courier_share, conn).await?;
+            Ok(())
+        }))?;
         Ok(())
     }
 
@@ -281,59 +1080,211 @@ impl Orders {
         Ok(())
     }
 
+    /// tips are set by the restaurant once the order is delivered,
+    /// a finalized order books the difference on the courier's earnings
+    pub async fn set_tip (
+        data: &Tip,
+        restaurant_id: i64,
+        conn: &PgConnection,
+    ) -> Result<()> {
+        require!(data.tip >= 0, "tip can't be negative");
+        conn.transaction::<(), ApiError, _>(|| futures::executor::block_on(async {
+            // finalization books the tip it reads, so the delta is taken
+            // from the row once a concurrent finalization is done with it
+            let order = orders::table
+                .filter(orders::id.eq(data.order_id))
+                .for_update()
+                .get_result::<Self>(conn)?;
+            require!(order.restaurant_id == Some(restaurant_id), "not your order");
+            require!(!order.is_split, "tips of split orders go to the parcels");
+            require!(order.status == OrderStatus::Delivered ||
+                order.status == OrderStatus::Success, "order is not delivered");
+            diesel::update(orders::table.filter(orders::id.eq(order.id)))
+                .set(orders::tip.eq(data.tip))
+                .execute(conn)?;
+            let delta = data.tip - order.tip;
+            if order.status == OrderStatus::Success && delta != 0 {
+                let payout = OrderPayout::get(order.id, conn).await?;
+                if let Some(courier_id) = payout.courier_id {
+                    LedgerTransaction::post(&Posting {
+                        kind: LedgerEntryKind::Tip,
+                        courier_id,
+                        account: LedgerAccount::CourierSalary,
+                        amount: delta,
+                        order_id: Some(order.id),
+                        actor_role: Some("restaurant".to_string()),
+                        actor_id: Some(restaurant_id),
+                        comment: None,
+                    }, conn).await?;
+                }
+            }
+            Ok(())
+        }))
+    }
+
     pub async fn finalize_order (
         data: &Finalization,
         conn: &PgConnection,
     ) -> Result<()> {
-        diesel::sql_query("select * from finalize_order($1,$2,$3,$4);")
-            .bind::<Bigint,_>(data.order_id)
-            .bind::<Bool,_>(data.is_success)
-            .bind::<Bool,_>(data.courier_fault)
-            .bind::<Varchar,_>(&data.comment)
-            .execute(conn)?;
-        Ok(())
+        conn.transaction::<(), ApiError, _>(|| futures::executor::block_on(async {
+            // a concurrent finalization waits here and then sees the stamp
+            let order = orders::table
+                .filter(orders::id.eq(data.order_id))
+                .for_update()
+                .get_result::<Self>(conn)?;
+            require!(order.finalize_datetime.is_none(), "order already finalized");
+            require!(!order.is_split, "split orders are finalized by parcels");
+            require!(order.status != OrderStatus::Returning, "returns are closed by the restaurant's confirmation");
+            // the query below only marks a delivered order successful, the payout
+            // must not be stamped for an order it leaves as it is
+            require!(order.status == OrderStatus::Delivered || !data.is_success,
+                "order is not delivered yet");
+            let payout = OrderPayout::finalize(data.order_id, conn).await?;
+            diesel::sql_query("select * from finalize_order($1,$2,$3,$4);")
+                .bind::<Bigint,_>(data.order_id)
+                .bind::<Bool,_>(data.is_success)
+                .bind::<Bool,_>(data.courier_fault)
+                .bind::<Varchar,_>(&data.comment)
+                .execute(conn)?;
+            let order = Self::get_by_id(data.order_id, conn).await?;
+            require!(order.finalize_datetime.is_some(), "order was not finalized");
+            if let Some(OrderPayout { courier_id: Some(courier_id), courier_payout, .. }) = payout {
+                LedgerTransaction::post_order(
+                    order.id,
+                    courier_id,
+                    &order.status,
+                    order.method,
+                    courier_payout.unwrap_or(0),
+                    order.order_price,
+                    order.tip,
+                    conn,
+                ).await?;
+            }
+            Ok(())
+        }))
     }
 }
 
+/// session ends are warned about this long in advance
+const SESSION_WARNING_MINUTES: i32 = 15;
+/// a session may be opened this long before its start
+const SESSION_EARLY_START_MINUTES: i64 = 15;
+/// the longest session a courier may open
+const MAX_SESSION_HOURS: i64 = 16;
+
+/// orders that keep the session from being closed
+pub fn active_statuses() -> Vec<OrderStatus> {
+    vec![
+        OrderStatus::CourierConfirmation,
+        OrderStatus::Cooking,
+        OrderStatus::ReadyForDelivery,
+        OrderStatus::Delivering,
+        OrderStatus::Returning,
+    ]
+}
+
 #[derive(Serialize,Deserialize,Clone,Insertable)]
 #[table_name="sessions"]
 pub struct NewSession {
    pub courier_id: i64,
-   pub start_time: chrono::NaiveTime,
-   pub end_time: chrono::NaiveTime,
+   pub start_at: chrono::NaiveDateTime,
+   pub end_at: chrono::NaiveDateTime,
    pub has_terminal: bool,
    pub transport: TransportType,
+   #[serde(skip_deserializing)]
+   pub booking_id: Option<i64>,
+   #[serde(skip_deserializing)]
+   pub override_by: Option<i64>,
 }
 
 #[derive(Serialize,Deserialize,Clone,Queryable)]
 pub struct Sessions {
    pub id: i64,
    pub courier_id: i64,
-   pub start_time: chrono::NaiveTime,
-   pub end_time: chrono::NaiveTime,
-   pub session_day: chrono::NaiveDate,
-   pub end_real_time: Option<chrono::NaiveTime>,
    pub has_terminal: bool,
    pub transport: TransportType,
+   /// the shift slot booking the session was opened in
+   pub booking_id: Option<i64>,
+   /// the curator who let the courier work without a booking
+   pub override_by: Option<i64>,
+   pub start_at: chrono::NaiveDateTime,
+   /// planned end, may be on the next day
+   pub end_at: chrono::NaiveDateTime,
+   /// actual end, none while the session is open
+   pub ended_at: Option<chrono::NaiveDateTime>,
+   pub end_warned: bool,
 }
 
 impl Sessions {
+    /// couriers work only within a booked shift slot, unless a curator overrides it
     pub async fn new(
-        data: &NewSession,
+        data: &mut NewSession,
         conn: &PgConnection,
     ) -> Result<()> {
-        diesel::insert_into(sessions::table)
-            .values(data)
-            .execute(conn)?;
+        let booking = match data.override_by {
+            Some(_) => None,
+            None => Some(ShiftBooking::current(data.courier_id, conn).await?.ok_or(ApiError {
+                code: 400,
+                message: "no booked shift slot right now".to_string(),
+                error_type: ErrorType::InternalError,
+            })?),
+        };
+        if let Some((booking, slot)) = &booking {
+            require!(booking.transport == data.transport, "transport differs from the booking");
+            data.booking_id = Some(booking.id);
+            data.end_at = slot.end_at;
+        }
+        require!(data.start_at < data.end_at, "session ends before it starts");
+        require!(data.end_at - data.start_at <= chrono::Duration::hours(MAX_SESSION_HOURS),
+            "session is too long");
+        let db_now = diesel::select(diesel::dsl::now)
+            .get_result::<chrono::NaiveDateTime>(conn)?;
+        require!(data.end_at > db_now, "session is already over");
+        require!(data.start_at <= db_now + chrono::Duration::minutes(SESSION_EARLY_START_MINUTES),
+            "session can't be opened in advance");
+        let open = sessions::table
+            .filter(sessions::courier_id.eq(data.courier_id))
+            .filter(sessions::ended_at.is_null())
+            .count()
+            .get_result::<i64>(conn)?;
+        require!(open == 0, "previous session is still open");
+        let session_id = diesel::insert_into(sessions::table)
+            .values(&*data)
+            .returning(sessions::id)
+            .get_result::<i64>(conn)?;
+        if let Some((booking, _)) = booking {
+            ShiftBooking::attend(booking.id, session_id, conn).await?;
+        }
         Ok(())
     }
 
+    pub async fn has_active_orders(
+        session_id: i64,
+        conn: &PgConnection,
+    ) -> Result<bool> {
+        let r = orders::table
+            .filter(orders::session_id.eq(session_id))
+            .filter(orders::status.eq_any(active_statuses()))
+            .count()
+            .get_result::<i64>(conn)?;
+        Ok(r > 0)
+    }
+
     pub async fn finish(
         courier_id: i64,
         conn: &PgConnection,
         conn_redis: &mut redis::Connection,
     ) -> Result<()> {
         use crate::temp::db::rm_coords;
+        let session_id = sessions::table
+            .filter(sessions::courier_id.eq(courier_id))
+            .filter(sessions::ended_at.is_null())
+            .select(sessions::id)
+            .first::<i64>(conn)
+            .optional()?;
+        if let Some(id) = session_id {
+            require!(!Self::has_active_orders(id, conn).await?, "finish your orders first");
+        }
         rm_coords(courier_id, conn_redis).await?;
         diesel::sql_query("select * from end_session($1);")
             .bind::<Bigint,_>(courier_id)
@@ -341,6 +1292,50 @@ impl Sessions {
         Ok(())
     }
 
+    /// notifies couriers whose sessions are about to end
+    pub async fn warn_ending(
+        conn: &PgConnection,
+    ) -> Result<usize> {
+        use diesel::dsl::IntervalDsl;
+        let due = sessions::table
+            .filter(sessions::ended_at.is_null())
+            .filter(sessions::end_warned.eq(false))
+            .filter(sessions::end_at.le(diesel::dsl::now + SESSION_WARNING_MINUTES.minutes()))
+            .get_results::<Self>(conn)?;
+        for s in due.iter() {
+            diesel::insert_into(notifications::table)
+                .values((
+                    notifications::title.eq("Смена заканчивается"),
+                    notifications::message.eq(format!("Смена закончится в {}",
+                        s.end_at.format("%H:%M"))),
+                    notifications::courier_id.eq(s.courier_id),
+                ))
+                .execute(conn)?;
+            diesel::update(sessions::table.filter(sessions::id.eq(s.id)))
+                .set(sessions::end_warned.eq(true))
+                .execute(conn)?;
+        }
+        Ok(due.len())
+    }
+
+    /// closes the sessions past their end, those with orders on hand
+    /// stay open until the orders are done; returns the couriers let go
+    pub async fn close_expired(
+        conn: &PgConnection,
+    ) -> Result<Vec<i64>> {
+        use diesel::dsl::{exists, not};
+        let r = diesel::update(sessions::table
+                .filter(sessions::ended_at.is_null())
+                .filter(sessions::end_at.le(diesel::dsl::now))
+                .filter(not(exists(orders::table
+                    .filter(orders::session_id.eq(sessions::id.nullable()))
+                    .filter(orders::status.eq_any(active_statuses()))))))
+            .set(sessions::ended_at.eq(diesel::dsl::now.nullable()))
+            .returning(sessions::courier_id)
+            .get_results::<i64>(conn)?;
+        Ok(r)
+    }
+
     pub async fn get_by_courier(
         courier_id: i64,
         conn: &PgConnection,
@@ -29,7 +29,30 @@ use super::db::{
     Notification,
     Finalization,
     CourierRating,
+    Tip,
+    NewOrderRequest,
+    OrderItem,
+    PhoneAccess,
+    Unmask,
+    OrderReturn,
+    OrderEvent,
+    ReturnReport,
+    ReturnConfirmation,
+    Split,
 };
+use crate::enum_types::TransportType;
+use crate::tariffs::db::{
+    Tariff,
+    TariffInput,
+};
+use crate::eta::db::{
+    route,
+    Eta,
+};
+use crate::users::db::CatalogItem;
+use crate::temp::db::Coords;
+use crate::zones::db::DeliveryZone;
+use crate::availability::db::CourierAvailability;
 
 #[derive(Serialize,Deserialize)]
 pub struct CourierToOrder {
@@ -48,6 +71,19 @@ pub fn ordering_routes(cfg: &mut web::ServiceConfig) {
         .route("/set_ready_for_delivery_order", web::post().to(ready_for_delivery_order))
         .route("/rate_courier", web::post().to(rate_courier))
         .route("/finalize_order", web::post().to(finalize_order))
+        .route("/set_tip", web::post().to(set_tip))
+        .route("/get_items", web::post().to(get_items))
+        .route("/split_order", web::post().to(split_order))
+        .route("/get_parcels", web::post().to(get_parcels))
+        .route("/get_nearest_couriers", web::post().to(get_nearest_couriers))
+        .route("/call_client", web::post().to(call_client))
+        .route("/courier_arrived", web::post().to(courier_arrived))
+        .route("/get_events", web::post().to(get_events))
+        .route("/report_return", web::post().to(report_return))
+        .route("/confirm_return", web::post().to(confirm_return))
+        .route("/get_returns", web::post().to(get_returns))
+        .route("/unmask_phone", web::post().to(unmask_phone))
+        .route("/get_phone_access_log", web::post().to(get_phone_access_log))
         .route("/send_notification", web::post().to(send_notifiaction))
         .route("/get_notifications", web::post().to(get_notifications))
         .route("/get_by_restaurant", web::post().to(get_by_restaurant))
@@ -67,6 +103,13 @@ pub struct Dist {
     from_lng: f64,
     to_lat: f64,
     to_lng: f64,
+    #[serde(default)]
+    transport: Option<TransportType>,
+    #[serde(default)]
+    is_big_order: bool,
+    /// applies the restaurant's delivery zones when given
+    #[serde(default)]
+    restaurant_id: Option<i64>,
 }
 
 pub async fn get_distance_pay(
@@ -74,23 +117,28 @@ pub async fn get_distance_pay(
     data: web::Json<Dist>,
     conn: web::Data<DbPool>,
 ) -> Result<HttpResponse> {
-        use serde_json::Value;
-        let url = format!(
-            "http://router.project-osrm.org/route/v1/car/{},{};{},{}",data.from_lng,
-            data.from_lat,data.to_lng,data.to_lat); 
-        let resp: Value = reqwest::get(&url)
-            .await?
-            .json()
-            .await?;
-        let dist = resp["routes"][0]["distance"]
-                .as_f64()
-                .ok_or_else(||ApiError {
-                    code: 500,
-                    message: "error in getting coords from json".to_string(),
-                    error_type: ErrorType::InternalError,
-                })?;
+        let conn = conn.get()?;
+        let from = Coords { lat: data.from_lat, lng: data.from_lng };
+        let to = Coords { lat: data.to_lat, lng: data.to_lng };
+        let route = route(&from, &to, &TransportType::Car).await?;
+        let zone = match data.restaurant_id {
+            Some(id) => DeliveryZone::locate(id, &to, route.distance, &conn).await?,
+            None => None,
+        };
+        let tariff = Tariff::current(&conn).await?;
+        let quote = tariff.quote(&TariffInput {
+            distance: route.distance,
+            transport: data.transport.clone(),
+            is_big_order: data.is_big_order,
+            datetime: chrono::Utc::now().naive_utc(),
+            zone_multiplier: zone.as_ref().map(|z| z.tariff_multiplier).unwrap_or(1.0),
+        });
         Ok(HttpResponse::Ok().json(json!({
-            "cost": dist as i64
+            "cost": quote.delivery_price,
+            "courier_share": quote.courier_share,
+            "tariff_version": quote.tariff_version,
+            "distance": route.distance as i64,
+            "zone_id": zone.map(|z| z.id),
         })))
 }
 
@@ -175,10 +223,14 @@ pub async fn set_delivered_order(
     auth: Auth,
     form: web::Json<Id>,
     conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
     let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
     Orders::set_delivered(form.id,&conn).await?;
+    Eta::invalidate(form.id, &mut conn_redis).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut conn_redis).await;
     Ok(HttpResponse::Ok().json(()))
 }
 
@@ -189,7 +241,8 @@ pub async fn rate_courier(
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
     let conn = conn.get()?;
-    CourierRating::new(&form, &conn).await?;
+    let mut form = form.into_inner();
+    CourierRating::new(&mut form, auth.id, &conn).await?;
     Ok(HttpResponse::Ok().json(()))
 }
 
@@ -204,29 +257,202 @@ pub async fn finalize_order(
     Ok(HttpResponse::Ok().json(()))
 }
 
-pub async fn new_order(
+pub async fn set_tip(
     auth: Auth,
-    form: web::Json<NewOrder>,
+    form: web::Json<Tip>,
     conn: web::Data<DbPool>,
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
-    require!(auth.id == form.restaurant_id, "not your id");
     let conn = conn.get()?;
-    let mut form = form.into_inner();
-    Orders::create_order(&mut form, &conn).await?;
+    Orders::set_tip(&form, auth.id, &conn).await?;
     Ok(HttpResponse::Ok().json(()))
 }
 
+pub async fn courier_arrived(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = OrderEvent::courier_arrived(form.id, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_events(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let order = Orders::get_by_id(form.id, &conn).await?;
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()) ||
+        (auth.roles.contains(&"restaurant".to_string()) && order.restaurant_id == Some(auth.id)) ||
+        (auth.roles.contains(&"courier".to_string()) && order.is_carried_by(auth.id, &conn).await?),
+        "not permitted");
+    let r = OrderEvent::get_by_order(order.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn report_return(
+    auth: Auth,
+    form: web::Json<ReturnReport>,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
+    let r = OrderReturn::report(&form, auth.id, &conn, &mut conn_redis).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn confirm_return(
+    auth: Auth,
+    form: web::Json<ReturnConfirmation>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = OrderReturn::confirm(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_returns(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let order = Orders::get_by_id(form.id, &conn).await?;
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()) ||
+        (auth.roles.contains(&"restaurant".to_string()) && order.restaurant_id == Some(auth.id)) ||
+        (auth.roles.contains(&"courier".to_string()) && order.is_carried_by(auth.id, &conn).await?),
+        "not permitted");
+    let r = OrderReturn::get_by_order(order.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn call_client(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = PhoneAccess::call_client(form.id, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "client_phone": r,
+    })))
+}
+
+pub async fn unmask_phone(
+    auth: Auth,
+    form: web::Json<Unmask>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = PhoneAccess::unmask(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "client_phone": r,
+    })))
+}
+
+pub async fn get_phone_access_log(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = PhoneAccess::get_by_order(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn split_order(
+    auth: Auth,
+    form: web::Json<Split>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let order = Orders::get_by_id(form.order_id, &conn).await?;
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()) ||
+        (auth.roles.contains(&"restaurant".to_string()) && order.restaurant_id == Some(auth.id)),
+        "not permitted");
+    let r = Orders::split(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_parcels(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let order = Orders::get_by_id(form.id, &conn).await?;
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()) ||
+        (auth.roles.contains(&"restaurant".to_string()) && order.restaurant_id == Some(auth.id)),
+        "not permitted");
+    let r = Orders::get_parcels(order.id, &conn).await?
+        .into_iter()
+        .map(Orders::masked)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_items(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let order = Orders::get_by_id(form.id, &conn).await?;
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()) ||
+        (auth.roles.contains(&"restaurant".to_string()) && order.restaurant_id == Some(auth.id)) ||
+        (auth.roles.contains(&"courier".to_string()) && order.is_carried_by(auth.id, &conn).await?),
+        "not permitted");
+    let r = OrderItem::get_by_order(order.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn new_order(
+    auth: Auth,
+    form: web::Json<NewOrderRequest>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
+    require!(auth.id == form.order.restaurant_id, "not your id");
+    let conn = conn.get()?;
+    let NewOrderRequest { order: mut form, items } = form.into_inner();
+    let items = CatalogItem::resolve(form.restaurant_id, &items, &conn).await?;
+    let r = Orders::create_order(&mut form, &items, &conn).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "order_id": r.id,
+        "tracking_token": r.tracking_token,
+    })))
+}
+
 pub async fn get_orders(
     auth: Auth,
     form: web::Json<OrderRequest>,
     conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
     require!(auth.id == form.courier_id,"not you");
     let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
     let form = form.into_inner();
-    let r = Orders::get_suggested(&form, &conn).await?;
+    CourierAvailability::refresh(auth.id, &conn, &mut conn_redis).await?
+        .require_dispatchable()?;
+    let r = Orders::get_suggested(&form, &conn, &mut conn_redis).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut conn_redis).await;
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -249,11 +475,14 @@ pub async fn refuse_order(
     auth: Auth,
     form: web::Json<Id>,
     conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
     let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
     let form = form.into_inner();
     let r = Orders::refuse_order(form.id, auth.id, &conn).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut conn_redis).await;
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -261,11 +490,37 @@ pub async fn take_order(
     auth: Auth,
     form: web::Json<Id>,
     conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
     let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
     let form = form.into_inner();
     let r = Orders::take_order(form.id, auth.id, &conn).await?;
+    Eta::invalidate(form.id, &mut conn_redis).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut conn_redis).await;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+#[derive(Deserialize)]
+pub struct NearestToOrder {
+    pub order_id: i64,
+    #[serde(default)]
+    pub count: Option<usize>,
+}
+
+/// candidates for a waiting order, for curators dispatching by hand
+pub async fn get_nearest_couriers(
+    auth: Auth,
+    form: web::Json<NearestToOrder>,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
+    let r = Orders::nearest_couriers(form.order_id, form.count.unwrap_or(10), &conn, &mut conn_redis).await?;
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -277,7 +532,7 @@ pub async fn get_orders_by_session_id(
     require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
     let conn = conn.get()?;
     let form = form.into_inner();
-    let r = Orders::get_orders_by_session_id(form.id, &conn).await?;
+    let r = Orders::get_orders_by_session_id(form.id, auth.id, &conn).await?;
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -285,11 +540,15 @@ pub async fn pick_order(
     auth: Auth,
     form: web::Json<Id>,
     conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
     let conn = conn.get()?;
+    let mut conn_redis = redis_conn.get()?;
     let form = form.into_inner();
     let r = Orders::pick_order(form.id, auth.id, &conn).await?;
+    Eta::invalidate(form.id, &mut conn_redis).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut conn_redis).await;
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -297,11 +556,14 @@ pub async fn create_session(
     auth: Auth,
     mut form: web::Json<NewSession>,
     conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
     form.courier_id = auth.id;
     let conn = conn.get()?;
-    let r = Sessions::new(&form, &conn).await?;
+    let mut conn_redis = redis_conn.get()?;
+    let r = Sessions::new(&mut form, &conn).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut conn_redis).await;
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -314,6 +576,7 @@ pub async fn cancel_session(
     let conn = conn.get()?;
     let mut conn_redis = redis_conn.get()?;
     let r = Sessions::finish(auth.id, &conn, &mut conn_redis).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut conn_redis).await;
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -0,0 +1,441 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use crate::schema::{
+    commission_rules,
+    payment_surcharges,
+    return_rules,
+    order_payouts,
+    couriers,
+    orders,
+    sessions,
+};
+use crate::enum_types::*;
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct CommissionTier {
+    /// successful orders the courier has finished this month
+    pub min_orders: i64,
+    pub kind: CommissionKind,
+    pub amount: i64,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct CommissionRule {
+    pub id: i64,
+    pub kind: CommissionKind,
+    /// none means the rule applies to couriers of every tier
+    pub courier_tier: Option<i16>,
+    /// kopecks for `Fixed`, basis points for `Percentage`
+    pub amount: i64,
+    pub tiers: serde_json::Value,
+    pub effective_from: chrono::NaiveDateTime,
+    pub admin_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="commission_rules"]
+pub struct NewCommissionRule {
+    pub kind: CommissionKind,
+    pub courier_tier: Option<i16>,
+    pub amount: i64,
+    #[serde(default = "empty_tiers")]
+    pub tiers: serde_json::Value,
+    pub effective_from: chrono::NaiveDateTime,
+}
+
+fn empty_tiers() -> serde_json::Value {
+    json!([])
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct PaymentSurcharge {
+    pub id: i64,
+    pub method: PayMethod,
+    /// basis points of the courier share
+    pub percent: i64,
+    pub effective_from: chrono::NaiveDateTime,
+    pub admin_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="payment_surcharges"]
+pub struct NewPaymentSurcharge {
+    pub method: PayMethod,
+    pub percent: i64,
+    pub effective_from: chrono::NaiveDateTime,
+}
+
+/// how the courier is paid for bringing an undeliverable order back
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct ReturnRule {
+    pub id: i64,
+    /// percent of the order's courier share
+    pub share_percent: i16,
+    pub base_fee: i64,
+    /// cash orders bring no money back, the return leg may be left unpaid
+    pub pay_cash_orders: bool,
+    pub effective_from: chrono::NaiveDateTime,
+    pub admin_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="return_rules"]
+pub struct NewReturnRule {
+    pub share_percent: i16,
+    pub base_fee: i64,
+    pub pay_cash_orders: bool,
+    pub effective_from: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct OrderPayout {
+    pub id: i64,
+    pub order_id: i64,
+    pub base_share: i64,
+    pub surcharge_id: Option<i64>,
+    pub surcharge: i64,
+    pub courier_id: Option<i64>,
+    pub commission_rule_id: Option<i64>,
+    pub commission: Option<i64>,
+    pub courier_payout: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+    pub finalize_datetime: Option<chrono::NaiveDateTime>,
+}
+
+fn apply(kind: CommissionKind, amount: i64, share: i64) -> i64 {
+    match kind {
+        CommissionKind::Fixed => amount,
+        CommissionKind::Percentage => share * amount / 10000,
+        CommissionKind::Tiered => 0,
+    }
+}
+
+impl NewCommissionRule {
+    fn validate(&self) -> Result<()> {
+        require!(self.amount >= 0, "amount can't be negative");
+        if self.kind == CommissionKind::Tiered {
+            let tiers: Vec<CommissionTier> = serde_json::from_value(self.tiers.clone())
+                .map_err(|_| ApiError {
+                    code: 400,
+                    message: "tiers must be a list of {min_orders, kind, amount}".to_string(),
+                    error_type: ErrorType::InternalError,
+                })?;
+            require!(!tiers.is_empty(), "tiered rule needs tiers");
+            require!(tiers.iter().all(|t| t.kind != CommissionKind::Tiered && t.amount >= 0),
+                "tier must be fixed or percentage");
+        }
+        Ok(())
+    }
+}
+
+impl CommissionRule {
+    pub async fn new(
+        data: &NewCommissionRule,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        data.validate()?;
+        let r = diesel::insert_into(commission_rules::table)
+            .values((data, commission_rules::admin_id.eq(admin_id)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = commission_rules::table
+            .order(commission_rules::effective_from.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// tier specific rule wins over a general one
+    pub async fn effective(
+        courier_tier: i16,
+        at: chrono::NaiveDateTime,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let specific = commission_rules::table
+            .filter(commission_rules::courier_tier.eq(courier_tier))
+            .filter(commission_rules::effective_from.le(at))
+            .order(commission_rules::effective_from.desc())
+            .first::<Self>(conn)
+            .optional()?;
+        if let Some(r) = specific {
+            return Ok(r);
+        }
+        let r = commission_rules::table
+            .filter(commission_rules::courier_tier.is_null())
+            .filter(commission_rules::effective_from.le(at))
+            .order(commission_rules::effective_from.desc())
+            .first::<Self>(conn)
+            .map_err(|_| ApiError {
+                code: 500,
+                message: "commission rule is not configured".to_string(),
+                error_type: ErrorType::InternalError,
+            })?;
+        Ok(r)
+    }
+
+    pub fn commission(&self, share: i64, monthly_orders: i64) -> i64 {
+        match self.kind {
+            CommissionKind::Tiered => {
+                let tiers: Vec<CommissionTier> = serde_json::from_value(self.tiers.clone())
+                    .unwrap_or_default();
+                tiers.iter()
+                    .filter(|t| t.min_orders <= monthly_orders)
+                    .max_by_key(|t| t.min_orders)
+                    .map(|t| apply(t.kind, t.amount, share))
+                    .unwrap_or(0)
+            },
+            kind => apply(kind, self.amount, share),
+        }
+    }
+}
+
+impl PaymentSurcharge {
+    pub async fn new(
+        data: &NewPaymentSurcharge,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(data.percent >= 0, "percent can't be negative");
+        let r = diesel::insert_into(payment_surcharges::table)
+            .values((data, payment_surcharges::admin_id.eq(admin_id)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = payment_surcharges::table
+            .order(payment_surcharges::effective_from.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn effective(
+        method: PayMethod,
+        at: chrono::NaiveDateTime,
+        conn: &PgConnection,
+    ) -> Result<Option<Self>> {
+        let r = payment_surcharges::table
+            .filter(payment_surcharges::method.eq(method))
+            .filter(payment_surcharges::effective_from.le(at))
+            .order(payment_surcharges::effective_from.desc())
+            .first::<Self>(conn)
+            .optional()?;
+        Ok(r)
+    }
+}
+
+impl ReturnRule {
+    pub async fn new(
+        data: &NewReturnRule,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(data.share_percent >= 0, "percent can't be negative");
+        require!(data.base_fee >= 0, "fee can't be negative");
+        let r = diesel::insert_into(return_rules::table)
+            .values((data, return_rules::admin_id.eq(admin_id)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = return_rules::table
+            .order(return_rules::effective_from.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn effective(
+        at: chrono::NaiveDateTime,
+        conn: &PgConnection,
+    ) -> Result<Option<Self>> {
+        let r = return_rules::table
+            .filter(return_rules::effective_from.le(at))
+            .order(return_rules::effective_from.desc())
+            .first::<Self>(conn)
+            .optional()?;
+        Ok(r)
+    }
+
+    pub fn payout(&self, share: i64, method: PayMethod) -> i64 {
+        if method == PayMethod::Cash && !self.pay_cash_orders {
+            return 0;
+        }
+        share * self.share_percent as i64 / 100 + self.base_fee
+    }
+}
+
+impl OrderPayout {
+    /// records the share and payment surcharge at order creation,
+    /// returns the share the restaurant is charged
+    pub async fn open(
+        order_id: i64,
+        base_share: i64,
+        method: PayMethod,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let now = chrono::Utc::now().naive_utc();
+        let surcharge = PaymentSurcharge::effective(method, now, conn).await?;
+        let amount = surcharge.as_ref()
+            .map(|s| base_share * s.percent / 10000)
+            .unwrap_or(0);
+        let r = diesel::insert_into(order_payouts::table)
+            .values((
+                order_payouts::order_id.eq(order_id),
+                order_payouts::base_share.eq(base_share),
+                order_payouts::surcharge_id.eq(surcharge.map(|s| s.id)),
+                order_payouts::surcharge.eq(amount),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get(
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = order_payouts::table
+            .filter(order_payouts::order_id.eq(order_id))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// the payout of an order created before payouts were recorded
+    /// is opened from the share stored on the order
+    async fn get_or_backfill(
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = order_payouts::table
+            .filter(order_payouts::order_id.eq(order_id))
+            .get_result::<Self>(conn)
+            .optional()?;
+        if let Some(r) = r {
+            return Ok(r);
+        }
+        let base_share = orders::table
+            .filter(orders::id.eq(order_id))
+            .select(orders::courier_share)
+            .get_result::<i64>(conn)?;
+        let r = diesel::insert_into(order_payouts::table)
+            .values((
+                order_payouts::order_id.eq(order_id),
+                order_payouts::base_share.eq(base_share),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// puts a new base share on the payout, the surcharge follows it
+    /// at the rate stamped when the payout was opened
+    pub async fn reprice(
+        order_id: i64,
+        base_share: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let payout = Self::get_or_backfill(order_id, conn).await?;
+        let percent = match payout.surcharge_id {
+            Some(id) => payment_surcharges::table
+                .filter(payment_surcharges::id.eq(id))
+                .select(payment_surcharges::percent)
+                .get_result::<i64>(conn)?,
+            None => 0,
+        };
+        let r = diesel::update(order_payouts::table
+            .filter(order_payouts::order_id.eq(order_id)))
+            .set((
+                order_payouts::base_share.eq(base_share),
+                order_payouts::surcharge.eq(base_share * percent / 10000),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// stamps the commission rule in force at finalization and
+    /// the resulting courier payout
+    pub async fn finalize(
+        order_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Option<Self>> {
+        use chrono::Datelike;
+        let courier = orders::table
+            .inner_join(sessions::table.on(orders::session_id.eq(sessions::id.nullable())))
+            .inner_join(couriers::table.on(couriers::id.eq(sessions::courier_id)))
+            .filter(orders::id.eq(order_id))
+            .select((couriers::id, couriers::tier))
+            .get_result::<(i64, i16)>(conn)
+            .optional()?;
+        let (courier_id, tier) = match courier {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let payout = Self::get_or_backfill(order_id, conn).await?;
+        let now = chrono::Utc::now().naive_utc();
+        let month_start = now.date().with_day(1).expect("first day").and_hms(0, 0, 0);
+        let monthly_orders = orders::table
+            .inner_join(sessions::table.on(orders::session_id.eq(sessions::id.nullable())))
+            .filter(sessions::courier_id.eq(courier_id))
+            .filter(orders::status.eq(OrderStatus::Success))
+            .filter(orders::finalize_datetime.ge(month_start))
+            .count()
+            .get_result::<i64>(conn)?;
+        let rule = CommissionRule::effective(tier, now, conn).await?;
+        let share = payout.base_share + payout.surcharge;
+        let commission = rule.commission(share, monthly_orders);
+        let r = diesel::update(order_payouts::table
+            .filter(order_payouts::order_id.eq(order_id)))
+            .set((
+                order_payouts::courier_id.eq(courier_id),
+                order_payouts::commission_rule_id.eq(rule.id),
+                order_payouts::commission.eq(commission),
+                order_payouts::courier_payout.eq(share - commission),
+                order_payouts::finalize_datetime.eq(now),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(Some(r))
+    }
+
+    /// pays the return leg by the return rule in force, without commission
+    pub async fn finalize_return(
+        order_id: i64,
+        courier_id: i64,
+        method: PayMethod,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let payout = Self::get_or_backfill(order_id, conn).await?;
+        let now = chrono::Utc::now().naive_utc();
+        let amount = ReturnRule::effective(now, conn)
+            .await?
+            .map(|rule| rule.payout(payout.base_share + payout.surcharge, method))
+            .unwrap_or(0);
+        let r = diesel::update(order_payouts::table
+            .filter(order_payouts::order_id.eq(order_id)))
+            .set((
+                order_payouts::courier_id.eq(courier_id),
+                order_payouts::commission.eq(0),
+                order_payouts::courier_payout.eq(amount),
+                order_payouts::finalize_datetime.eq(now),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+}
@@ -0,0 +1,143 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use crate::users::db::Couriers;
+use super::db::{
+    CommissionRule,
+    NewCommissionRule,
+    PaymentSurcharge,
+    NewPaymentSurcharge,
+    ReturnRule,
+    NewReturnRule,
+    OrderPayout,
+};
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct CourierTier {
+    courier_id: i64,
+    tier: i16,
+}
+
+pub fn payouts_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/payouts")
+        .route("/new_commission_rule", web::post().to(new_commission_rule))
+        .route("/get_commission_rules", web::post().to(get_commission_rules))
+        .route("/new_surcharge", web::post().to(new_surcharge))
+        .route("/get_surcharges", web::post().to(get_surcharges))
+        .route("/new_return_rule", web::post().to(new_return_rule))
+        .route("/get_return_rules", web::post().to(get_return_rules))
+        .route("/get_order_payout", web::post().to(get_order_payout))
+        .route("/set_courier_tier", web::post().to(set_courier_tier))
+    );
+}
+
+pub async fn new_commission_rule(
+    auth: Auth,
+    form: web::Json<NewCommissionRule>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = CommissionRule::new(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_commission_rules(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CommissionRule::get_all(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn new_surcharge(
+    auth: Auth,
+    form: web::Json<NewPaymentSurcharge>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = PaymentSurcharge::new(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_surcharges(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = PaymentSurcharge::get_all(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn new_return_rule(
+    auth: Auth,
+    form: web::Json<NewReturnRule>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = ReturnRule::new(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_return_rules(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = ReturnRule::get_all(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_order_payout(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = OrderPayout::get(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn set_courier_tier(
+    auth: Auth,
+    form: web::Json<CourierTier>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = Couriers::set_tier(form.courier_id, form.tier, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
@@ -0,0 +1,347 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::sql_types::*;
+use std::collections::HashMap;
+
+use crate::schema::{
+    courier_rating,
+    rating_criteria,
+    restaurant_ratings,
+};
+use crate::enum_types::*;
+use crate::ordering::db::Orders;
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct RatingCriterion {
+    pub id: i64,
+    pub name: String,
+    pub is_active: bool,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="rating_criteria"]
+pub struct NewRatingCriterion {
+    pub name: String,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct RestaurantRating {
+    pub id: i64,
+    pub order_id: i64,
+    pub courier_id: i64,
+    pub restaurant_id: i64,
+    pub criterion_id: i64,
+    pub score: i16,
+    pub comment: Option<String>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct CriterionScore {
+    pub criterion_id: i64,
+    pub score: i16,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct RestaurantRatingForm {
+    pub order_id: i64,
+    pub scores: Vec<CriterionScore>,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct RestaurantScore {
+    #[sql_type="Bigint"]
+    pub restaurant_id: i64,
+    #[sql_type="Varchar"]
+    pub restaurant_name: String,
+    #[sql_type="Bigint"]
+    pub ratings_count: i64,
+    #[sql_type="Nullable<Double>"]
+    pub rating: Option<f64>,
+    /// average seconds couriers waited at pickup
+    #[sql_type="Nullable<Double>"]
+    pub avg_wait: Option<f64>,
+    #[sql_type="Nullable<Double>"]
+    pub wait_score: Option<f64>,
+    /// rating and wait score combined, 1..5
+    #[sql_type="Nullable<Double>"]
+    pub score: Option<f64>,
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct CriterionAverage {
+    #[sql_type="Bigint"]
+    pub criterion_id: i64,
+    #[sql_type="Varchar"]
+    pub criterion_name: String,
+    #[sql_type="Bigint"]
+    pub ratings_count: i64,
+    #[sql_type="Double"]
+    pub avg_score: f64,
+}
+
+/// ratings lose half of their weight every `RATING_HALF_LIFE_DAYS`
+const RATING_HALF_LIFE_DAYS: f64 = 30.0;
+/// below this many ratings the courier has no score yet
+const MIN_RATINGS: usize = 5;
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct CourierRatingEntry {
+    pub order_id: i64,
+    pub look: i16,
+    pub politeness: i16,
+    pub comment: Option<String>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct CourierRatingBreakdown {
+    pub courier_id: i64,
+    pub ratings_count: usize,
+    /// decayed average of both criteria, none until `MIN_RATINGS` are collected
+    pub rating: Option<f64>,
+    pub look: Option<f64>,
+    pub politeness: Option<f64>,
+    /// how many ratings of each score from 1 to 5, both criteria together
+    pub distribution: [i64; 5],
+    pub recent: Vec<CourierRatingEntry>,
+}
+
+/// a row sent together with the rating of its courier
+#[derive(Serialize,Clone)]
+pub struct WithCourierRating<T> {
+    #[serde(flatten)]
+    pub row: T,
+    /// decayed average, none until `MIN_RATINGS` are collected or without a courier
+    pub courier_rating: Option<f64>,
+    pub courier_ratings_count: usize,
+}
+
+impl CourierRatingBreakdown {
+    pub async fn get(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let entries = courier_rating::table
+            .filter(courier_rating::courier_id.eq(courier_id))
+            .order(courier_rating::creation_datetime.desc())
+            .select((
+                courier_rating::order_id,
+                courier_rating::look,
+                courier_rating::politeness,
+                courier_rating::comment,
+                courier_rating::creation_datetime,
+            ))
+            .get_results::<CourierRatingEntry>(conn)?;
+        Ok(Self::compute(courier_id, entries, chrono::Utc::now().naive_utc()))
+    }
+
+    /// breakdowns of several couriers read in one query
+    pub async fn get_many(
+        courier_ids: &[i64],
+        conn: &PgConnection,
+    ) -> Result<HashMap<i64, Self>> {
+        let rows = courier_rating::table
+            .filter(courier_rating::courier_id.eq_any(courier_ids))
+            .order(courier_rating::creation_datetime.desc())
+            .select((
+                courier_rating::courier_id,
+                (
+                    courier_rating::order_id,
+                    courier_rating::look,
+                    courier_rating::politeness,
+                    courier_rating::comment,
+                    courier_rating::creation_datetime,
+                ),
+            ))
+            .get_results::<(i64, CourierRatingEntry)>(conn)?;
+        let mut entries: HashMap<i64, Vec<CourierRatingEntry>> = courier_ids.iter()
+            .map(|id| (*id, vec![]))
+            .collect();
+        for (courier_id, e) in rows {
+            entries.entry(courier_id).or_default().push(e);
+        }
+        let now = chrono::Utc::now().naive_utc();
+        Ok(entries.into_iter()
+            .map(|(id, e)| (id, Self::compute(id, e, now)))
+            .collect())
+    }
+
+    /// adds the courier rating to rows of a view, `courier_id` tells whose it is
+    pub async fn attach<T>(
+        rows: Vec<T>,
+        courier_id: impl Fn(&T) -> Option<i64>,
+        conn: &PgConnection,
+    ) -> Result<Vec<WithCourierRating<T>>> {
+        let mut ids = rows.iter().filter_map(|r| courier_id(r)).collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        let ratings = Self::get_many(&ids, conn).await?;
+        Ok(rows.into_iter()
+            .map(|row| {
+                let rating = courier_id(&row).and_then(|id| ratings.get(&id));
+                WithCourierRating {
+                    courier_rating: rating.and_then(|r| r.rating),
+                    courier_ratings_count: rating.map(|r| r.ratings_count).unwrap_or(0),
+                    row,
+                }
+            })
+            .collect())
+    }
+
+    pub fn compute(
+        courier_id: i64,
+        entries: Vec<CourierRatingEntry>,
+        now: chrono::NaiveDateTime,
+    ) -> Self {
+        let mut distribution = [0; 5];
+        let (mut weight, mut look, mut politeness) = (0.0, 0.0, 0.0);
+        for e in entries.iter() {
+            let age = (now - e.creation_datetime).num_seconds().max(0) as f64 / 86400.0;
+            let w = 0.5f64.powf(age / RATING_HALF_LIFE_DAYS);
+            weight += w;
+            look += w * e.look as f64;
+            politeness += w * e.politeness as f64;
+            for score in [e.look, e.politeness].iter() {
+                if (1..=5).contains(score) {
+                    distribution[(*score - 1) as usize] += 1;
+                }
+            }
+        }
+        let enough = entries.len() >= MIN_RATINGS && weight > 0.0;
+        let look = if enough { Some(look / weight) } else { None };
+        let politeness = if enough { Some(politeness / weight) } else { None };
+        Self {
+            courier_id,
+            ratings_count: entries.len(),
+            rating: look.and_then(|l| politeness.map(|p| (l + p) / 2.0)),
+            look,
+            politeness,
+            distribution,
+            recent: entries.into_iter().take(20).collect(),
+        }
+    }
+}
+
+impl RatingCriterion {
+    pub async fn new(
+        data: &NewRatingCriterion,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(!data.name.is_empty(), "name is empty");
+        let r = diesel::insert_into(rating_criteria::table)
+            .values(data)
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn toggle(
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::update(rating_criteria::table.filter(rating_criteria::id.eq(id)))
+            .set(rating_criteria::is_active.eq(diesel::dsl::not(rating_criteria::is_active)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_active(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = rating_criteria::table
+            .filter(rating_criteria::is_active.eq(true))
+            .order(rating_criteria::id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = rating_criteria::table
+            .order(rating_criteria::id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl RestaurantRating {
+    /// the courier rates the pickup once the order left the restaurant
+    pub async fn new(
+        data: &RestaurantRatingForm,
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        require!(!data.scores.is_empty(), "no scores");
+        require!(data.scores.iter().all(|s| s.score >= 1 && s.score <= 5), "score must be from 1 to 5");
+        let order = Orders::get_by_id(data.order_id, conn).await?;
+        require!(order.is_carried_by(courier_id, conn).await?, "not your order");
+        require!(match order.status {
+            OrderStatus::Delivering |
+            OrderStatus::Delivered |
+            OrderStatus::Success |
+            OrderStatus::Returning |
+            OrderStatus::Returned => true,
+            _ => false,
+        }, "order is not picked up yet");
+        let restaurant_id = order.restaurant_id.ok_or(ApiError {
+            code: 400,
+            message: "order has no restaurant".to_string(),
+            error_type: ErrorType::InternalError,
+        })?;
+        let active = RatingCriterion::get_active(conn).await?;
+        require!(data.scores.iter().all(|s| active.iter().any(|c| c.id == s.criterion_id)),
+            "unknown criterion");
+        let rows = data.scores.iter()
+            .map(|s| (
+                restaurant_ratings::order_id.eq(order.id),
+                restaurant_ratings::courier_id.eq(courier_id),
+                restaurant_ratings::restaurant_id.eq(restaurant_id),
+                restaurant_ratings::criterion_id.eq(s.criterion_id),
+                restaurant_ratings::score.eq(s.score),
+                restaurant_ratings::comment.eq(&data.comment),
+            ))
+            .collect::<Vec<_>>();
+        let r = diesel::insert_into(restaurant_ratings::table)
+            .values(&rows)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl RestaurantScore {
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = diesel::sql_query("select * from restaurant_scores ORDER BY score NULLS LAST;")
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl CriterionAverage {
+    pub async fn get_by_restaurant(
+        restaurant_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = diesel::sql_query("SELECT
+                c.id as criterion_id,
+                c.name as criterion_name,
+                COUNT(r.id) as ratings_count,
+                AVG(r.score)::DOUBLE PRECISION as avg_score
+            FROM restaurant_ratings r JOIN rating_criteria c ON c.id = r.criterion_id
+            WHERE r.restaurant_id = $1
+            GROUP BY c.id ORDER BY c.id;")
+            .bind::<Bigint,_>(restaurant_id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
@@ -0,0 +1,127 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use super::db::{
+    RatingCriterion,
+    NewRatingCriterion,
+    RestaurantRating,
+    RestaurantRatingForm,
+    RestaurantScore,
+    CriterionAverage,
+    CourierRatingBreakdown,
+};
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+pub fn ratings_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/ratings")
+        .route("/new_criterion", web::post().to(new_criterion))
+        .route("/toggle_criterion", web::post().to(toggle_criterion))
+        .route("/get_criteria", web::post().to(get_criteria))
+        .route("/rate_restaurant", web::post().to(rate_restaurant))
+        .route("/get_restaurant_scores", web::post().to(get_restaurant_scores))
+        .route("/get_restaurant_details", web::post().to(get_restaurant_details))
+        .route("/get_courier_breakdown", web::post().to(get_courier_breakdown))
+    );
+}
+
+pub async fn new_criterion(
+    auth: Auth,
+    form: web::Json<NewRatingCriterion>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = RatingCriterion::new(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn toggle_criterion(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = RatingCriterion::toggle(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_criteria(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let r = if auth.roles.contains(&"admin".to_string()) {
+        RatingCriterion::get_all(&conn).await?
+    } else {
+        RatingCriterion::get_active(&conn).await?
+    };
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn rate_restaurant(
+    auth: Auth,
+    form: web::Json<RestaurantRatingForm>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = RestaurantRating::new(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_restaurant_scores(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = RestaurantScore::get_all(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_restaurant_details(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CriterionAverage::get_by_restaurant(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_courier_breakdown(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string()) ||
+        (auth.roles.contains(&"courier".to_string()) && auth.id == form.id),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CourierRatingBreakdown::get(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
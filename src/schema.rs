@@ -27,6 +27,116 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    break_rules (id) {
+        id -> Int8,
+        max_breaks -> Int2,
+        max_minutes -> Int4,
+        effective_from -> Timestamp,
+        admin_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    cash_handovers (id) {
+        id -> Int8,
+        courier_id -> Int8,
+        session_id -> Nullable<Int8>,
+        declared_amount -> Int8,
+        received_amount -> Nullable<Int8>,
+        discrepancy -> Nullable<Int8>,
+        status -> Handoverstatus,
+        actor_role -> Nullable<Varchar>,
+        actor_id -> Nullable<Int8>,
+        comment -> Nullable<Varchar>,
+        creation_datetime -> Timestamp,
+        confirm_datetime -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    cash_limits (id) {
+        id -> Int8,
+        courier_tier -> Nullable<Int2>,
+        courier_id -> Nullable<Int8>,
+        amount -> Int8,
+        admin_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    catalog_items (id) {
+        id -> Int8,
+        restaurant_id -> Int8,
+        name -> Varchar,
+        price -> Int8,
+        weight -> Nullable<Int4>,
+        size_class -> Sizeclass,
+        is_fragile -> Bool,
+        is_drink -> Bool,
+        is_hot -> Bool,
+        is_available -> Bool,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    chat_templates (id) {
+        id -> Int8,
+        role -> Nullable<Varchar>,
+        body -> Varchar,
+        is_active -> Bool,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    commission_rules (id) {
+        id -> Int8,
+        kind -> Commissionkind,
+        courier_tier -> Nullable<Int2>,
+        amount -> Int8,
+        tiers -> Jsonb,
+        effective_from -> Timestamp,
+        admin_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    courier_availability_log (id) {
+        id -> Int8,
+        courier_id -> Int8,
+        status -> Availabilitystatus,
+        session_id -> Nullable<Int8>,
+        started_at -> Timestamp,
+        ended_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use crate::enum_types::*;
@@ -37,6 +147,9 @@ table! {
         order_id -> Int8,
         look -> Int2,
         politeness -> Int2,
+        comment -> Nullable<Varchar>,
+        restaurant_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
     }
 }
 
@@ -55,14 +168,10 @@ table! {
         is_warned -> Bool,
         is_deleted -> Bool,
         is_in_order -> Bool,
-        current_rate_amount -> Int8,
-        current_rate_count -> Int8,
         picture -> Nullable<Varchar>,
-        cash -> Int8,
-        term -> Int8,
-        salary -> Int8,
         creation_datetime -> Timestamp,
         email -> Varchar,
+        tier -> Int2,
     }
 }
 
@@ -118,6 +227,91 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    delivery_zones (id) {
+        id -> Int8,
+        restaurant_id -> Nullable<Int8>,
+        city -> Nullable<Varchar>,
+        name -> Varchar,
+        geometry -> Jsonb,
+        tariff_multiplier -> Float8,
+        max_distance -> Nullable<Int4>,
+        is_active -> Bool,
+        admin_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    disputes (id) {
+        id -> Int8,
+        order_id -> Int8,
+        opened_by_role -> Varchar,
+        opened_by_id -> Int8,
+        category -> Disputecategory,
+        description -> Varchar,
+        attachments -> Array<Text>,
+        status -> Disputestatus,
+        reviewer_role -> Nullable<Varchar>,
+        reviewer_id -> Nullable<Int8>,
+        previous_status -> Nullable<Orderstatus>,
+        new_status -> Nullable<Orderstatus>,
+        adjustment_id -> Nullable<Int8>,
+        resolution -> Nullable<Varchar>,
+        creation_datetime -> Timestamp,
+        resolve_datetime -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    ledger_entries (id) {
+        id -> Int8,
+        transaction_id -> Int8,
+        account -> Ledgeraccount,
+        courier_id -> Int8,
+        amount -> Int8,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    ledger_transactions (id) {
+        id -> Int8,
+        kind -> Ledgerentrykind,
+        courier_id -> Int8,
+        order_id -> Nullable<Int8>,
+        actor_role -> Nullable<Varchar>,
+        actor_id -> Nullable<Int8>,
+        comment -> Nullable<Varchar>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    location_points (id) {
+        id -> Int8,
+        courier_id -> Int8,
+        session_id -> Nullable<Int8>,
+        lat -> Float8,
+        lng -> Float8,
+        recorded_at -> Timestamp,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use crate::enum_types::*;
@@ -127,6 +321,7 @@ table! {
         title -> Varchar,
         message -> Varchar,
         creation_datetime -> Timestamp,
+        courier_id -> Nullable<Int8>,
     }
 }
 
@@ -166,6 +361,126 @@ table! {
         take_datetime -> Nullable<Timestamp>,
         delivery_datetime -> Nullable<Timestamp>,
         creation_datetime -> Timestamp,
+        tracking_token -> Nullable<Varchar>,
+        delivery_price -> Int8,
+        tariff_version -> Nullable<Int8>,
+        tip -> Int8,
+        zone_id -> Nullable<Int8>,
+        parent_id -> Nullable<Int8>,
+        is_split -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    order_events (id) {
+        id -> Int8,
+        order_id -> Int8,
+        kind -> Ordereventkind,
+        status -> Nullable<Orderstatus>,
+        courier_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+        dispute_id -> Nullable<Int8>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    order_items (id) {
+        id -> Int8,
+        order_id -> Int8,
+        catalog_item_id -> Nullable<Int8>,
+        name -> Varchar,
+        quantity -> Int4,
+        unit_price -> Int8,
+        weight -> Nullable<Int4>,
+        size_class -> Sizeclass,
+        is_fragile -> Bool,
+        is_drink -> Bool,
+        is_hot -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    order_message_reads (order_id, reader_role, reader_id) {
+        order_id -> Int8,
+        reader_role -> Varchar,
+        reader_id -> Int8,
+        last_read_id -> Int8,
+        read_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    order_messages (id) {
+        id -> Int8,
+        order_id -> Int8,
+        author_role -> Varchar,
+        author_id -> Int8,
+        body -> Varchar,
+        template_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    order_payouts (id) {
+        id -> Int8,
+        order_id -> Int8,
+        base_share -> Int8,
+        surcharge_id -> Nullable<Int8>,
+        surcharge -> Int8,
+        courier_id -> Nullable<Int8>,
+        commission_rule_id -> Nullable<Int8>,
+        commission -> Nullable<Int8>,
+        courier_payout -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+        finalize_datetime -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    order_returns (id) {
+        id -> Int8,
+        order_id -> Int8,
+        courier_id -> Int8,
+        reason -> Returnreason,
+        comment -> Nullable<Varchar>,
+        lat -> Nullable<Float8>,
+        lng -> Nullable<Float8>,
+        report_datetime -> Timestamp,
+        confirm_datetime -> Nullable<Timestamp>,
+        restaurant_comment -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    payment_surcharges (id) {
+        id -> Int8,
+        method -> Paymethod,
+        percent -> Int8,
+        effective_from -> Timestamp,
+        admin_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
     }
 }
 
@@ -180,6 +495,48 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    phone_access_log (id) {
+        id -> Int8,
+        order_id -> Int8,
+        actor_role -> Varchar,
+        actor_id -> Int8,
+        reason -> Nullable<Varchar>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    rating_criteria (id) {
+        id -> Int8,
+        name -> Varchar,
+        is_active -> Bool,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    restaurant_ratings (id) {
+        id -> Int8,
+        order_id -> Int8,
+        courier_id -> Int8,
+        restaurant_id -> Int8,
+        criterion_id -> Int8,
+        score -> Int2,
+        comment -> Nullable<Varchar>,
+        creation_datetime -> Timestamp,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use crate::enum_types::*;
@@ -198,6 +555,7 @@ table! {
         is_deleted -> Bool,
         creation_datetime -> Timestamp,
         email -> Varchar,
+        city -> Nullable<Varchar>,
     }
 }
 
@@ -224,6 +582,35 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    return_rules (id) {
+        id -> Int8,
+        share_percent -> Int2,
+        base_fee -> Int8,
+        pay_cash_orders -> Bool,
+        effective_from -> Timestamp,
+        admin_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    session_breaks (id) {
+        id -> Int8,
+        session_id -> Int8,
+        courier_id -> Int8,
+        started_at -> Timestamp,
+        ended_at -> Nullable<Timestamp>,
+        auto_resumed -> Bool,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use crate::enum_types::*;
@@ -231,40 +618,169 @@ table! {
     sessions (id) {
         id -> Int8,
         courier_id -> Int8,
-        start_time -> Time,
-        end_time -> Time,
-        session_day -> Date,
-        end_real_time -> Nullable<Time>,
         has_terminal -> Bool,
         transport -> Transporttype,
+        booking_id -> Nullable<Int8>,
+        override_by -> Nullable<Int8>,
+        start_at -> Timestamp,
+        end_at -> Timestamp,
+        ended_at -> Nullable<Timestamp>,
+        end_warned -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    shift_bookings (id) {
+        id -> Int8,
+        slot_id -> Int8,
+        courier_id -> Int8,
+        transport -> Transporttype,
+        status -> Shiftbookingstatus,
+        session_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    shift_slots (id) {
+        id -> Int8,
+        zone_id -> Nullable<Int8>,
+        start_at -> Timestamp,
+        end_at -> Timestamp,
+        car_quota -> Int4,
+        bicycle_quota -> Int4,
+        feet_quota -> Int4,
+        is_cancelled -> Bool,
+        curator_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::enum_types::*;
+
+    tariffs (id) {
+        id -> Int8,
+        version -> Int8,
+        base_fee -> Int8,
+        per_km -> Int8,
+        car_multiplier -> Float8,
+        bicycle_multiplier -> Float8,
+        feet_multiplier -> Float8,
+        big_order_surcharge -> Int8,
+        time_multipliers -> Jsonb,
+        min_price -> Int8,
+        courier_percent -> Int2,
+        admin_id -> Nullable<Int8>,
+        creation_datetime -> Timestamp,
     }
 }
 
+joinable!(break_rules -> admins (admin_id));
+joinable!(cash_handovers -> couriers (courier_id));
+joinable!(cash_handovers -> sessions (session_id));
+joinable!(cash_limits -> admins (admin_id));
+joinable!(cash_limits -> couriers (courier_id));
+joinable!(catalog_items -> restaurants (restaurant_id));
+joinable!(commission_rules -> admins (admin_id));
+joinable!(courier_availability_log -> couriers (courier_id));
+joinable!(courier_availability_log -> sessions (session_id));
 joinable!(courier_rating -> couriers (courier_id));
 joinable!(courier_rating -> orders (order_id));
+joinable!(courier_rating -> restaurants (restaurant_id));
 joinable!(couriers_approvals -> couriers (courier_id));
 joinable!(couriers_approvals -> orders (order_id));
 joinable!(couriers_xls_reports -> couriers (courier_id));
+joinable!(delivery_zones -> admins (admin_id));
+joinable!(delivery_zones -> restaurants (restaurant_id));
+joinable!(disputes -> ledger_transactions (adjustment_id));
+joinable!(disputes -> orders (order_id));
+joinable!(ledger_entries -> couriers (courier_id));
+joinable!(ledger_entries -> ledger_transactions (transaction_id));
+joinable!(ledger_transactions -> orders (order_id));
+joinable!(location_points -> couriers (courier_id));
+joinable!(location_points -> sessions (session_id));
+joinable!(notifications -> couriers (courier_id));
+joinable!(order_events -> couriers (courier_id));
+joinable!(order_events -> disputes (dispute_id));
+joinable!(order_events -> orders (order_id));
+joinable!(order_items -> catalog_items (catalog_item_id));
+joinable!(order_items -> orders (order_id));
+joinable!(order_message_reads -> orders (order_id));
+joinable!(order_messages -> chat_templates (template_id));
+joinable!(order_messages -> orders (order_id));
+joinable!(order_payouts -> commission_rules (commission_rule_id));
+joinable!(order_payouts -> couriers (courier_id));
+joinable!(order_payouts -> orders (order_id));
+joinable!(order_payouts -> payment_surcharges (surcharge_id));
+joinable!(order_returns -> couriers (courier_id));
+joinable!(order_returns -> orders (order_id));
+joinable!(orders -> delivery_zones (zone_id));
 joinable!(orders -> restaurants (restaurant_id));
 joinable!(orders -> sessions (session_id));
+joinable!(phone_access_log -> orders (order_id));
+joinable!(restaurant_ratings -> couriers (courier_id));
+joinable!(restaurant_ratings -> orders (order_id));
+joinable!(restaurant_ratings -> rating_criteria (criterion_id));
+joinable!(restaurant_ratings -> restaurants (restaurant_id));
 joinable!(restaurants_xls_reports -> restaurants (restaurant_id));
+joinable!(return_rules -> admins (admin_id));
+joinable!(session_breaks -> couriers (courier_id));
+joinable!(session_breaks -> sessions (session_id));
 joinable!(sessions -> couriers (courier_id));
+joinable!(shift_bookings -> couriers (courier_id));
+joinable!(shift_bookings -> shift_slots (slot_id));
+joinable!(shift_slots -> delivery_zones (zone_id));
 
 allow_tables_to_appear_in_same_query!(
     admins,
     auth,
+    break_rules,
+    cash_handovers,
+    cash_limits,
+    catalog_items,
+    chat_templates,
+    commission_rules,
+    courier_availability_log,
     courier_rating,
     couriers,
     couriers_approvals,
     couriers_for_curators_xls_reports,
     couriers_xls_reports,
     curators,
+    delivery_zones,
+    disputes,
+    ledger_entries,
+    ledger_transactions,
+    location_points,
     notifications,
     notifications_to_couriers,
+    order_events,
+    order_items,
+    order_message_reads,
+    order_messages,
+    order_payouts,
+    order_returns,
     orders,
+    payment_surcharges,
     pending_files,
+    phone_access_log,
+    rating_criteria,
+    restaurant_ratings,
     restaurants,
     restaurants_for_curators_xls_reports,
     restaurants_xls_reports,
+    return_rules,
+    session_breaks,
     sessions,
+    shift_bookings,
+    shift_slots,
+    tariffs,
 );
@@ -0,0 +1,347 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::sql_types::*;
+use diesel::dsl::IntervalDsl;
+
+use crate::schema::{
+    shift_bookings,
+    shift_slots,
+};
+use crate::enum_types::*;
+
+/// how early a courier may open a session before the slot starts
+const SLOT_GRACE_MINUTES: i32 = 15;
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct ShiftSlot {
+    pub id: i64,
+    pub zone_id: Option<i64>,
+    pub start_at: chrono::NaiveDateTime,
+    pub end_at: chrono::NaiveDateTime,
+    pub car_quota: i32,
+    pub bicycle_quota: i32,
+    pub feet_quota: i32,
+    pub is_cancelled: bool,
+    pub curator_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+/// the headcount is the sum of the transport quotas
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="shift_slots"]
+pub struct NewShiftSlot {
+    pub zone_id: Option<i64>,
+    pub start_at: chrono::NaiveDateTime,
+    pub end_at: chrono::NaiveDateTime,
+    #[serde(default)]
+    pub car_quota: i32,
+    #[serde(default)]
+    pub bicycle_quota: i32,
+    #[serde(default)]
+    pub feet_quota: i32,
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct ShiftBooking {
+    pub id: i64,
+    pub slot_id: i64,
+    pub courier_id: i64,
+    pub transport: TransportType,
+    pub status: ShiftBookingStatus,
+    pub session_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct NewShiftBooking {
+    pub slot_id: i64,
+    pub transport: TransportType,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct SlotFilter {
+    pub from: chrono::NaiveDateTime,
+    pub till: chrono::NaiveDateTime,
+    #[serde(default)]
+    pub zone_id: Option<i64>,
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct SlotLoad {
+    #[sql_type="Bigint"]
+    pub slot_id: i64,
+    #[sql_type="Nullable<Bigint>"]
+    pub zone_id: Option<i64>,
+    #[sql_type="Nullable<Varchar>"]
+    pub zone_name: Option<String>,
+    #[sql_type="Timestamp"]
+    pub start_at: chrono::NaiveDateTime,
+    #[sql_type="Timestamp"]
+    pub end_at: chrono::NaiveDateTime,
+    #[sql_type="Bigint"]
+    pub headcount: i64,
+    #[sql_type="Bigint"]
+    pub car_quota: i64,
+    #[sql_type="Bigint"]
+    pub bicycle_quota: i64,
+    #[sql_type="Bigint"]
+    pub feet_quota: i64,
+    #[sql_type="Bigint"]
+    pub car_booked: i64,
+    #[sql_type="Bigint"]
+    pub bicycle_booked: i64,
+    #[sql_type="Bigint"]
+    pub feet_booked: i64,
+    #[sql_type="Bigint"]
+    pub attended: i64,
+    #[sql_type="Bigint"]
+    pub no_shows: i64,
+}
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct NoShowCount {
+    #[sql_type="Bigint"]
+    pub courier_id: i64,
+    #[sql_type="Varchar"]
+    pub courier_name: String,
+    #[sql_type="Varchar"]
+    pub courier_surname: String,
+    #[sql_type="Bigint"]
+    pub booked: i64,
+    #[sql_type="Bigint"]
+    pub no_shows: i64,
+}
+
+impl ShiftSlot {
+    pub fn quota(&self, transport: TransportType) -> i32 {
+        match transport {
+            TransportType::Car => self.car_quota,
+            TransportType::Bicycle => self.bicycle_quota,
+            TransportType::Feet => self.feet_quota,
+        }
+    }
+
+    pub async fn new(
+        data: &NewShiftSlot,
+        curator_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(data.start_at < data.end_at, "slot ends before it starts");
+        require!(data.car_quota >= 0 && data.bicycle_quota >= 0 && data.feet_quota >= 0,
+            "quota can't be negative");
+        require!(data.car_quota + data.bicycle_quota + data.feet_quota > 0, "slot has no headcount");
+        let r = diesel::insert_into(shift_slots::table)
+            .values((data, shift_slots::curator_id.eq(curator_id)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// cancels the slot together with its bookings
+    pub async fn cancel(
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::update(shift_slots::table.filter(shift_slots::id.eq(id)))
+            .set(shift_slots::is_cancelled.eq(true))
+            .get_result::<Self>(conn)?;
+        diesel::update(shift_bookings::table
+                .filter(shift_bookings::slot_id.eq(id))
+                .filter(shift_bookings::status.eq(ShiftBookingStatus::Booked)))
+            .set(shift_bookings::status.eq(ShiftBookingStatus::Cancelled))
+            .execute(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get(
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = shift_slots::table
+            .filter(shift_slots::id.eq(id))
+            .get_result::<Self>(conn)
+            .optional()?;
+        r.ok_or(ApiError {
+            code: 404,
+            message: "slot not found".to_string(),
+            error_type: ErrorType::InternalError,
+        })
+    }
+}
+
+impl SlotLoad {
+    pub async fn get(
+        data: &SlotFilter,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = diesel::sql_query("select * from shift_slot_load
+            WHERE start_at < $2 AND end_at > $1 AND ($3 IS NULL OR zone_id = $3)
+            ORDER BY start_at, slot_id;")
+            .bind::<Timestamp,_>(data.from)
+            .bind::<Timestamp,_>(data.till)
+            .bind::<Nullable<Bigint>,_>(data.zone_id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl ShiftBooking {
+    pub async fn book(
+        data: &NewShiftBooking,
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        conn.transaction::<Self, ApiError, _>(|| {
+            // bookings of one slot queue up on its row, so the count below can't go stale
+            let slot = shift_slots::table
+                .filter(shift_slots::id.eq(data.slot_id))
+                .for_update()
+                .get_result::<ShiftSlot>(conn)
+                .optional()?
+                .ok_or(ApiError {
+                    code: 404,
+                    message: "slot not found".to_string(),
+                    error_type: ErrorType::InternalError,
+                })?;
+            require!(!slot.is_cancelled, "slot is cancelled");
+            let started = shift_slots::table
+                .filter(shift_slots::id.eq(slot.id))
+                .filter(shift_slots::start_at.le(diesel::dsl::now))
+                .count()
+                .get_result::<i64>(conn)?;
+            require!(started == 0, "slot has already started");
+            let booked = shift_bookings::table
+                .filter(shift_bookings::slot_id.eq(slot.id))
+                .filter(shift_bookings::transport.eq(data.transport))
+                .filter(shift_bookings::status.ne(ShiftBookingStatus::Cancelled))
+                .count()
+                .get_result::<i64>(conn)?;
+            require!(booked < slot.quota(data.transport) as i64, "no places left for this transport");
+            let overlapping = shift_bookings::table
+                .inner_join(shift_slots::table)
+                .filter(shift_bookings::courier_id.eq(courier_id))
+                .filter(shift_bookings::status.eq(ShiftBookingStatus::Booked))
+                .filter(shift_slots::start_at.lt(slot.end_at))
+                .filter(shift_slots::end_at.gt(slot.start_at))
+                .count()
+                .get_result::<i64>(conn)?;
+            require!(overlapping == 0, "you already booked a slot at this time");
+            let r = diesel::insert_into(shift_bookings::table)
+                .values((
+                    shift_bookings::slot_id.eq(slot.id),
+                    shift_bookings::courier_id.eq(courier_id),
+                    shift_bookings::transport.eq(data.transport),
+                ))
+                .get_result::<Self>(conn)?;
+            Ok(r)
+        })
+    }
+
+    pub async fn cancel(
+        id: i64,
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::update(shift_bookings::table
+                .filter(shift_bookings::id.eq(id))
+                .filter(shift_bookings::courier_id.eq(courier_id))
+                .filter(shift_bookings::status.eq(ShiftBookingStatus::Booked)))
+            .set(shift_bookings::status.eq(ShiftBookingStatus::Cancelled))
+            .get_result::<Self>(conn)
+            .optional()?;
+        r.ok_or(ApiError {
+            code: 404,
+            message: "booking not found".to_string(),
+            error_type: ErrorType::InternalError,
+        })
+    }
+
+    /// the courier's booking whose slot is running now or starts within the grace period
+    pub async fn current(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Option<(Self, ShiftSlot)>> {
+        let r = shift_bookings::table
+            .inner_join(shift_slots::table)
+            .filter(shift_bookings::courier_id.eq(courier_id))
+            .filter(shift_bookings::status.eq_any(vec![
+                ShiftBookingStatus::Booked,
+                ShiftBookingStatus::NoShow,
+            ]))
+            .filter(shift_slots::is_cancelled.eq(false))
+            .filter(shift_slots::start_at.le(diesel::dsl::now + SLOT_GRACE_MINUTES.minutes()))
+            .filter(shift_slots::end_at.gt(diesel::dsl::now))
+            .order(shift_slots::start_at)
+            .first::<(Self, ShiftSlot)>(conn)
+            .optional()?;
+        Ok(r)
+    }
+
+    /// a late courier who still shows up is no longer counted as a no-show
+    pub async fn attend(
+        id: i64,
+        session_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::update(shift_bookings::table.filter(shift_bookings::id.eq(id)))
+            .set((
+                shift_bookings::status.eq(ShiftBookingStatus::Attended),
+                shift_bookings::session_id.eq(session_id),
+            ))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_by_courier(
+        courier_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<(Self, ShiftSlot)>> {
+        let r = shift_bookings::table
+            .inner_join(shift_slots::table)
+            .filter(shift_bookings::courier_id.eq(courier_id))
+            .order(shift_slots::start_at.desc())
+            .get_results::<(Self, ShiftSlot)>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_by_slot(
+        slot_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = shift_bookings::table
+            .filter(shift_bookings::slot_id.eq(slot_id))
+            .order(shift_bookings::creation_datetime)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
+
+impl NoShowCount {
+    pub async fn get(
+        from: chrono::NaiveDateTime,
+        till: chrono::NaiveDateTime,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = diesel::sql_query("SELECT
+                c.id as courier_id,
+                c.name as courier_name,
+                c.surname as courier_surname,
+                COUNT(b.id) as booked,
+                COUNT(b.id) FILTER (WHERE b.status = 'NoShow') as no_shows
+            FROM shift_bookings b
+                JOIN shift_slots s ON s.id = b.slot_id
+                JOIN couriers c ON c.id = b.courier_id
+            WHERE b.status != 'Cancelled' AND s.start_at >= $1 AND s.start_at < $2
+            GROUP BY c.id
+            ORDER BY no_shows DESC, c.id;")
+            .bind::<Timestamp,_>(from)
+            .bind::<Timestamp,_>(till)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+}
@@ -0,0 +1,175 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use crate::ordering::db::{
+    NewSession,
+    Sessions,
+};
+use super::db::{
+    ShiftSlot,
+    NewShiftSlot,
+    ShiftBooking,
+    NewShiftBooking,
+    SlotFilter,
+    SlotLoad,
+    NoShowCount,
+};
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct Period {
+    from: chrono::NaiveDateTime,
+    till: chrono::NaiveDateTime,
+}
+
+pub fn shifts_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/shifts")
+        .route("/new_slot", web::post().to(new_slot))
+        .route("/cancel_slot", web::post().to(cancel_slot))
+        .route("/get_slots", web::post().to(get_slots))
+        .route("/book", web::post().to(book))
+        .route("/cancel_booking", web::post().to(cancel_booking))
+        .route("/get_my_bookings", web::post().to(get_my_bookings))
+        .route("/get_bookings_by_courier", web::post().to(get_bookings_by_courier))
+        .route("/get_bookings_by_slot", web::post().to(get_bookings_by_slot))
+        .route("/override_session", web::post().to(override_session))
+        .route("/get_no_shows", web::post().to(get_no_shows))
+    );
+}
+
+fn is_staff(auth: &Auth) -> bool {
+    auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string())
+}
+
+pub async fn new_slot(
+    auth: Auth,
+    form: web::Json<NewShiftSlot>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = ShiftSlot::new(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn cancel_slot(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = ShiftSlot::cancel(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_slots(
+    auth: Auth,
+    form: web::Json<SlotFilter>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth) ||
+        auth.roles.contains(&"courier".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = SlotLoad::get(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn book(
+    auth: Auth,
+    form: web::Json<NewShiftBooking>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = ShiftBooking::book(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn cancel_booking(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = ShiftBooking::cancel(form.id, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_my_bookings(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"courier".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = ShiftBooking::get_by_courier(auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_bookings_by_courier(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = ShiftBooking::get_by_courier(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_bookings_by_slot(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = ShiftBooking::get_by_slot(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+/// opens a session for a courier without a booked slot
+pub async fn override_session(
+    auth: Auth,
+    mut form: web::Json<NewSession>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    form.override_by = Some(auth.id);
+    let conn = conn.get()?;
+    let r = Sessions::new(&mut form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_no_shows(
+    auth: Auth,
+    form: web::Json<Period>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = NoShowCount::get(form.from, form.till, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
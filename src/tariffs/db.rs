@@ -0,0 +1,184 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use crate::schema::tariffs;
+use crate::enum_types::*;
+
+/// tariff windows are set in Moscow time, which has no DST
+const LOCAL_OFFSET_HOURS: i64 = 3;
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct TimeMultiplier {
+    pub from: chrono::NaiveTime,
+    pub till: chrono::NaiveTime,
+    pub multiplier: f64,
+}
+
+impl TimeMultiplier {
+    fn contains(&self, t: chrono::NaiveTime) -> bool {
+        if self.from <= self.till {
+            self.from <= t && t < self.till
+        } else {
+            // window spans midnight, e.g. 22:00 - 06:00
+            self.from <= t || t < self.till
+        }
+    }
+}
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct Tariff {
+    pub id: i64,
+    pub version: i64,
+    pub base_fee: i64,
+    pub per_km: i64,
+    pub car_multiplier: f64,
+    pub bicycle_multiplier: f64,
+    pub feet_multiplier: f64,
+    pub big_order_surcharge: i64,
+    pub time_multipliers: serde_json::Value,
+    pub min_price: i64,
+    pub courier_percent: i16,
+    pub admin_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name="tariffs"]
+pub struct NewTariff {
+    pub base_fee: i64,
+    pub per_km: i64,
+    pub car_multiplier: f64,
+    pub bicycle_multiplier: f64,
+    pub feet_multiplier: f64,
+    pub big_order_surcharge: i64,
+    pub time_multipliers: serde_json::Value,
+    pub min_price: i64,
+    pub courier_percent: i16,
+}
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct TariffInput {
+    /// metres
+    pub distance: f64,
+    pub transport: Option<TransportType>,
+    pub is_big_order: bool,
+    pub datetime: chrono::NaiveDateTime,
+    pub zone_multiplier: f64,
+}
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct Quote {
+    pub tariff_version: i64,
+    pub delivery_price: i64,
+    pub courier_share: i64,
+}
+
+impl NewTariff {
+    fn validate(&self) -> Result<Vec<TimeMultiplier>> {
+        let windows: Vec<TimeMultiplier> = serde_json::from_value(self.time_multipliers.clone())
+            .map_err(|_| ApiError {
+                code: 400,
+                message: "time_multipliers must be a list of {from, till, multiplier}".to_string(),
+                error_type: ErrorType::InternalError,
+            })?;
+        require!(self.base_fee >= 0 && self.per_km >= 0 &&
+            self.big_order_surcharge >= 0 && self.min_price >= 0, "prices can't be negative");
+        require!(self.car_multiplier > 0.0 && self.bicycle_multiplier > 0.0 &&
+            self.feet_multiplier > 0.0, "multipliers must be positive");
+        require!(windows.iter().all(|w| w.multiplier > 0.0), "multipliers must be positive");
+        require!(self.courier_percent >= 0 && self.courier_percent <= 100,
+            "courier_percent must be within 0..100");
+        Ok(windows)
+    }
+}
+
+impl Tariff {
+    /// tariffs are never edited in place: every change is a new version
+    pub async fn new(
+        data: &NewTariff,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        data.validate()?;
+        let r = conn.transaction::<Self, diesel::result::Error, _>(|| {
+            let version = tariffs::table
+                .select(diesel::dsl::max(tariffs::version))
+                .get_result::<Option<i64>>(conn)?
+                .unwrap_or(0) + 1;
+            diesel::insert_into(tariffs::table)
+                .values((
+                    data,
+                    tariffs::version.eq(version),
+                    tariffs::admin_id.eq(admin_id),
+                ))
+                .get_result::<Self>(conn)
+        })?;
+        Ok(r)
+    }
+
+    pub async fn current(
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = tariffs::table
+            .order(tariffs::version.desc())
+            .first::<Self>(conn)
+            .map_err(|_| ApiError {
+                code: 500,
+                message: "tariff is not configured".to_string(),
+                error_type: ErrorType::InternalError,
+            })?;
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = tariffs::table
+            .order(tariffs::version.desc())
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    fn transport_multiplier(&self, transport: &Option<TransportType>) -> f64 {
+        match transport {
+            Some(TransportType::Car) => self.car_multiplier,
+            Some(TransportType::Bicycle) => self.bicycle_multiplier,
+            Some(TransportType::Feet) => self.feet_multiplier,
+            None => 1.0,
+        }
+    }
+
+    fn time_multiplier(&self, datetime: chrono::NaiveDateTime) -> f64 {
+        let local = (datetime + chrono::Duration::hours(LOCAL_OFFSET_HOURS)).time();
+        serde_json::from_value::<Vec<TimeMultiplier>>(self.time_multipliers.clone())
+            .unwrap_or_default()
+            .iter()
+            .filter(|w| w.contains(local))
+            .map(|w| w.multiplier)
+            .fold(1.0, f64::max)
+    }
+
+    pub fn quote(&self, input: &TariffInput) -> Quote {
+        let km = input.distance / 1000.0;
+        let price = (self.base_fee as f64 + self.per_km as f64 * km)
+            * self.transport_multiplier(&input.transport)
+            * self.time_multiplier(input.datetime)
+            * input.zone_multiplier;
+        let mut price = price.round() as i64;
+        if input.is_big_order {
+            price += self.big_order_surcharge;
+        }
+        let price = std::cmp::max(price, self.min_price);
+        Quote {
+            tariff_version: self.version,
+            delivery_price: price,
+            courier_share: price * self.courier_percent as i64 / 100,
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use super::db::{
+    Tariff,
+    NewTariff,
+};
+
+pub fn tariffs_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/tariffs")
+        .route("/new", web::post().to(new_tariff))
+        .route("/get_current", web::post().to(get_current_tariff))
+        .route("/get_all", web::post().to(get_all_tariffs))
+    );
+}
+
+pub async fn new_tariff(
+    auth: Auth,
+    form: web::Json<NewTariff>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = Tariff::new(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_current_tariff(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let r = Tariff::current(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_all_tariffs(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = Tariff::get_all(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
@@ -7,11 +7,57 @@ use actix_web_dev::error::{
     ErrorType,
 };
 use serde::{Serialize,Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use crate::schema::{
+    location_points,
+    orders,
+    sessions,
+};
+use crate::enum_types::{
+    AvailabilityStatus,
+    TransportType,
+};
+use crate::availability::db::CourierAvailability;
 
 #[derive(Clone,Debug,Serialize,Deserialize)]
 pub struct Coords {
-    lat: f64,
-    lng: f64,
+    pub lat: f64,
+    pub lng: f64,
+}
+
+impl Coords {
+    /// great-circle distance in metres
+    pub fn distance_to(&self, other: &Coords) -> f64 {
+        let r = 6_371_000.0;
+        let d_lat = (other.lat - self.lat).to_radians();
+        let d_lng = (other.lng - self.lng).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) +
+            self.lat.to_radians().cos() * other.lat.to_radians().cos() *
+            (d_lng / 2.0).sin().powi(2);
+        2.0 * r * a.sqrt().asin()
+    }
+}
+
+#[derive(Clone,Debug,Serialize,Deserialize,Queryable)]
+pub struct LocationPoint {
+    pub id: i64,
+    pub courier_id: i64,
+    pub session_id: Option<i64>,
+    pub lat: f64,
+    pub lng: f64,
+    pub recorded_at: chrono::NaiveDateTime,
+}
+
+/// either a session or an order, the order's part of the track runs from
+/// taking it till finalization
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct TrackRequest {
+    #[serde(default)]
+    pub session_id: Option<i64>,
+    #[serde(default)]
+    pub order_id: Option<i64>,
 }
 
 #[derive(Clone,Debug,Serialize,Deserialize)]
@@ -22,10 +68,91 @@ pub struct CourierLocation {
 
 #[derive(Clone,Debug,Serialize,Deserialize)]
 pub struct CoordsWithStamp {
-    courier_id: i64,
-    lat: f64,
-    lng: f64,
-    timestamp: i64,
+    pub courier_id: i64,
+    pub lat: f64,
+    pub lng: f64,
+    pub timestamp: i64,
+}
+
+/// a position older than this no longer tells where the courier is,
+/// `LOCATION_TTL_SECONDS` overrides it
+pub const LOCATION_TTL_SECONDS: i64 = 120;
+/// positions this old are dropped from the map altogether
+pub const LOCATION_EXPIRY_SECONDS: i64 = 3600;
+
+pub fn location_ttl_seconds() -> i64 {
+    std::env::var("LOCATION_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(LOCATION_TTL_SECONDS)
+}
+
+/// sorted set of the same positions kept by GEOADD for radius searches
+const GEO_KEY: &str = "courier_geo";
+/// nearest couriers are looked for no farther than this, metres
+pub const NEAREST_SEARCH_RADIUS: f64 = 50_000.0;
+
+/// which couriers a radius search returns, idle ones by default
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct NearbyFilter {
+    #[serde(default = "NearbyFilter::idle")]
+    pub statuses: Vec<AvailabilityStatus>,
+    #[serde(default)]
+    pub transport: Option<Vec<TransportType>>,
+}
+
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct NearbyRequest {
+    pub lat: f64,
+    pub lng: f64,
+    /// metres, for the within-radius search
+    #[serde(default)]
+    pub radius: Option<f64>,
+    /// for the nearest-N search
+    #[serde(default)]
+    pub count: Option<usize>,
+    #[serde(flatten)]
+    pub filter: NearbyFilter,
+}
+
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct NearbyCourier {
+    pub courier_id: i64,
+    /// metres in a straight line
+    pub distance: f64,
+    pub lat: f64,
+    pub lng: f64,
+    pub status: AvailabilityStatus,
+    pub transport: TransportType,
+}
+
+impl NearbyFilter {
+    fn idle() -> Vec<AvailabilityStatus> {
+        vec![AvailabilityStatus::OnlineIdle]
+    }
+}
+
+/// a position as shown on the map
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct CourierPosition {
+    #[serde(flatten)]
+    pub coords: CoordsWithStamp,
+    pub stale: bool,
+}
+
+impl CoordsWithStamp {
+    fn age(&self) -> i64 {
+        chrono::Utc::now().timestamp() - self.timestamp
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.age() > location_ttl_seconds()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.age() > LOCATION_EXPIRY_SECONDS
+    }
 }
 
 pub async fn set_coords(loc: CourierLocation, conn: &mut redis::Connection) -> Result<()> {
@@ -35,29 +162,300 @@ pub async fn set_coords(loc: CourierLocation, conn: &mut redis::Connection) -> R
             lng: loc.location.lng,
             timestamp: chrono::Utc::now().timestamp(),
     }).expect("err rerealize"))?;
+    redis::cmd("GEOADD")
+        .arg(GEO_KEY)
+        .arg(loc.location.lng)
+        .arg(loc.location.lat)
+        .arg(loc.courier_id)
+        .query::<()>(conn)?;
     Ok(())
 }
 
+/// every position on the map flagged by freshness; malformed and expired
+/// entries are removed on the way
 pub async fn get_coords(
     conn: &mut redis::Connection
-) -> Result<Vec<CoordsWithStamp>> {
+) -> Result<Vec<CourierPosition>> {
     let v: Vec<(i64,String)> = conn.hgetall("courier")?;
-    println!("v: {:?}",v);
-    let r: Vec<CoordsWithStamp> = v
+    let mut r = vec![];
+    for (courier_id, f) in v {
+        match serde_json::from_str::<CoordsWithStamp>(&f) {
+            Ok(c) if !c.is_expired() => r.push(CourierPosition {
+                stale: c.is_stale(),
+                coords: c,
+            }),
+            Ok(_) => rm_coords(courier_id, conn).await?,
+            Err(e) => {
+                println!("malformed location of courier {}: {}", courier_id, e);
+                rm_coords(courier_id, conn).await?;
+            },
+        }
+    }
+    Ok(r)
+}
+
+/// positions fresh enough to be trusted
+pub async fn get_fresh_coords(
+    conn: &mut redis::Connection
+) -> Result<Vec<CoordsWithStamp>> {
+    let r = get_coords(conn).await?
         .into_iter()
-        .map(|(_,f)|{
-            serde_json::from_str(&f).unwrap()
-        })
+        .filter(|p| !p.stale)
+        .map(|p| p.coords)
         .collect();
-    println!("r: {:?}",r);
     Ok(r)
 }
 
+/// drops positions of couriers who have no open session
+pub async fn cleanup_coords(
+    conn: &PgConnection,
+    conn_redis: &mut redis::Connection,
+) -> Result<usize> {
+    let mut ids: Vec<i64> = conn_redis.hkeys("courier")?;
+    let in_geo: Vec<i64> = conn_redis.zrange(GEO_KEY, 0, -1)?;
+    ids.extend(in_geo);
+    ids.sort();
+    ids.dedup();
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let on_session = sessions::table
+        .filter(sessions::courier_id.eq_any(&ids))
+        .filter(sessions::ended_at.is_null())
+        .select(sessions::courier_id)
+        .get_results::<i64>(conn)?;
+    let mut r = 0;
+    for id in ids.into_iter().filter(|id| !on_session.contains(id)) {
+        rm_coords(id, conn_redis).await?;
+        r += 1;
+    }
+    Ok(r)
+}
+
+/// couriers within `radius` metres of the point, nearest first, that pass the filter
+pub async fn couriers_within(
+    point: &Coords,
+    radius: f64,
+    filter: &NearbyFilter,
+    conn: &PgConnection,
+    conn_redis: &mut redis::Connection,
+) -> Result<Vec<NearbyCourier>> {
+    require!(radius > 0.0, "radius must be positive");
+    search_nearby(point, radius, None, filter, conn, conn_redis).await
+}
+
+/// the radius search behind both lookups, the statuses are worked out
+/// nearest first and only until `limit` couriers have passed the filter
+async fn search_nearby(
+    point: &Coords,
+    radius: f64,
+    limit: Option<usize>,
+    filter: &NearbyFilter,
+    conn: &PgConnection,
+    conn_redis: &mut redis::Connection,
+) -> Result<Vec<NearbyCourier>> {
+    if limit == Some(0) {
+        return Ok(vec![]);
+    }
+    let found: Vec<(i64, f64, (f64, f64))> = redis::cmd("GEORADIUS")
+        .arg(GEO_KEY)
+        .arg(point.lng)
+        .arg(point.lat)
+        .arg(radius)
+        .arg("m")
+        .arg("WITHDIST")
+        .arg("WITHCOORD")
+        .arg("ASC")
+        .query(conn_redis)?;
+    if found.is_empty() {
+        return Ok(vec![]);
+    }
+    let ids = found.iter().map(|(id, _, _)| *id).collect::<Vec<_>>();
+    let transports = sessions::table
+        .filter(sessions::courier_id.eq_any(&ids))
+        .filter(sessions::ended_at.is_null())
+        .select((sessions::courier_id, sessions::transport))
+        .get_results::<(i64, TransportType)>(conn)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut r = vec![];
+    for (courier_id, distance, (lng, lat)) in found {
+        if limit.map(|l| r.len() >= l).unwrap_or(false) {
+            break;
+        }
+        let transport = match transports.get(&courier_id) {
+            Some(t) => *t,
+            None => continue,
+        };
+        if !filter.transport.as_ref().map(|t| t.contains(&transport)).unwrap_or(true) {
+            continue;
+        }
+        let (status, _) = CourierAvailability::compute(courier_id, conn, conn_redis).await?;
+        if !filter.statuses.contains(&status) {
+            continue;
+        }
+        r.push(NearbyCourier {
+            courier_id,
+            distance,
+            lat,
+            lng,
+            status,
+            transport,
+        });
+    }
+    Ok(r)
+}
+
+/// up to `count` couriers nearest to the point that pass the filter
+pub async fn nearest_couriers(
+    point: &Coords,
+    count: usize,
+    filter: &NearbyFilter,
+    conn: &PgConnection,
+    conn_redis: &mut redis::Connection,
+) -> Result<Vec<NearbyCourier>> {
+    search_nearby(point, NEAREST_SEARCH_RADIUS, Some(count), filter, conn, conn_redis).await
+}
+
+/// the courier's point in the radius index, the one dispatch measures from
+pub async fn geo_position(
+    courier_id: i64,
+    conn: &mut redis::Connection
+) -> Result<Option<Coords>> {
+    let v: Vec<Option<(f64, f64)>> = redis::cmd("GEOPOS")
+        .arg(GEO_KEY)
+        .arg(courier_id)
+        .query(conn)?;
+    Ok(v.into_iter().next().flatten().map(|(lng, lat)| Coords { lat, lng }))
+}
+
+pub async fn get_courier_coords(
+    courier_id: i64,
+    conn: &mut redis::Connection
+) -> Result<Option<CoordsWithStamp>> {
+    let v: Option<String> = conn.hget("courier", courier_id)?;
+    Ok(v.and_then(|f| serde_json::from_str::<CoordsWithStamp>(&f).ok())
+        .filter(|c| !c.is_expired()))
+}
+
 pub async fn rm_coords(
     courier_id: i64,
     conn: &mut redis::Connection
 ) -> Result<()> {
     conn.hdel("courier", courier_id)?;
+    conn.zrem(GEO_KEY, courier_id)?;
     Ok(())
 }
 
+/// days the location history is kept, `LOCATION_RETENTION_DAYS` overrides it
+pub const LOCATION_RETENTION_DAYS: i64 = 30;
+
+pub fn location_retention_days() -> i64 {
+    std::env::var("LOCATION_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(LOCATION_RETENTION_DAYS)
+}
+
+impl LocationPoint {
+    /// appends the point to the courier's history, only while on a session
+    pub async fn append(
+        loc: &CourierLocation,
+        conn: &PgConnection,
+    ) -> Result<()> {
+        let session_id = sessions::table
+            .filter(sessions::courier_id.eq(loc.courier_id))
+            .filter(sessions::ended_at.is_null())
+            .select(sessions::id)
+            .first::<i64>(conn)
+            .optional()?;
+        if let Some(session_id) = session_id {
+            diesel::insert_into(location_points::table)
+                .values((
+                    location_points::courier_id.eq(loc.courier_id),
+                    location_points::session_id.eq(session_id),
+                    location_points::lat.eq(loc.location.lat),
+                    location_points::lng.eq(loc.location.lng),
+                ))
+                .execute(conn)?;
+        }
+        Ok(())
+    }
+
+    pub async fn purge(
+        days: i64,
+        conn: &PgConnection,
+    ) -> Result<usize> {
+        use diesel::dsl::IntervalDsl;
+        let r = diesel::delete(location_points::table
+                .filter(location_points::recorded_at.lt(diesel::dsl::now - (days as i32).days())))
+            .execute(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_track(
+        data: &TrackRequest,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let mut query = location_points::table
+            .order(location_points::recorded_at)
+            .into_boxed();
+        match (data.session_id, data.order_id) {
+            (Some(session_id), None) => {
+                query = query.filter(location_points::session_id.eq(session_id));
+            },
+            (None, Some(order_id)) => {
+                let (session_id, taken, finalized) = orders::table
+                    .filter(orders::id.eq(order_id))
+                    .select((orders::session_id, orders::take_datetime, orders::finalize_datetime))
+                    .get_result::<(Option<i64>, Option<chrono::NaiveDateTime>, Option<chrono::NaiveDateTime>)>(conn)
+                    .optional()?
+                    .ok_or(ApiError {
+                        code: 404,
+                        message: "order not found".to_string(),
+                        error_type: ErrorType::InternalError,
+                    })?;
+                let (session_id, taken) = match (session_id, taken) {
+                    (Some(s), Some(t)) => (s, t),
+                    _ => return Ok(vec![]),
+                };
+                query = query
+                    .filter(location_points::session_id.eq(session_id))
+                    .filter(location_points::recorded_at.ge(taken));
+                if let Some(finalized) = finalized {
+                    query = query.filter(location_points::recorded_at.le(finalized));
+                }
+            },
+            _ => return Err(ApiError {
+                code: 400,
+                message: "either session or order is expected".to_string(),
+                error_type: ErrorType::InternalError,
+            }),
+        }
+        let r = query.get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// GeoJSON LineString feature of the points, `[lng, lat]` as GeoJSON wants
+    pub fn to_line_string(
+        data: &TrackRequest,
+        points: &[Self],
+    ) -> serde_json::Value {
+        json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "LineString",
+                "coordinates": points.iter().map(|p| [p.lng, p.lat]).collect::<Vec<_>>(),
+            },
+            "properties": {
+                "session_id": data.session_id.or(points.first().and_then(|p| p.session_id)),
+                "order_id": data.order_id,
+                "courier_id": points.first().map(|p| p.courier_id),
+                "timestamps": points.iter().map(|p| p.recorded_at).collect::<Vec<_>>(),
+                "started_at": points.first().map(|p| p.recorded_at),
+                "ended_at": points.last().map(|p| p.recorded_at),
+            },
+        })
+    }
+}
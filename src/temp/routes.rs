@@ -24,29 +24,53 @@ use super::db::{
     self as db,
     CoordsWithStamp,
     CourierLocation,
+    LocationPoint,
+    TrackRequest,
+    NearbyRequest,
+    Coords,
+    nearest_couriers,
+    couriers_within,
     rm_coords,
     get_coords,
+    get_fresh_coords,
     set_coords,
 };
+use crate::eta::db::Eta;
+use crate::breaks::db::SessionBreak;
+use crate::availability::db::CourierAvailability;
 
 pub fn location_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(web::scope("/location")
         .route("/add", web::post().to(add_location))
         .route("/remove", web::post().to(rm_location))
         .route("/get", web::post().to(get_location))
+        .route("/track", web::post().to(get_track))
+        .route("/nearest", web::post().to(get_nearest))
+        .route("/within", web::post().to(get_within))
     );
 }
 
 pub async fn add_location(
     auth: Auth,
     form: web::Json<CourierLocation>,
+    conn: web::Data<DbPool>,
     redis_conn: web::Data<RedisDbPool>,
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"courier".to_string()),"not courier"); 
     require!(auth.id == form.courier_id,"not your id"); 
-    let mut conn = redis_conn.get()?;
+    let conn = conn.get()?;
+    let mut redis_conn = redis_conn.get()?;
     let form = form.into_inner();
-    set_coords(form, &mut conn).await?;
+    // paused couriers stay off the map until they resume
+    if SessionBreak::is_paused(auth.id, &conn).await? {
+        return Ok(HttpResponse::Ok().json(""));
+    }
+    LocationPoint::append(&form, &conn).await?;
+    set_coords(form, &mut redis_conn).await?;
+    if let Err(e) = Eta::refresh_for_courier(auth.id, &conn, &mut redis_conn).await {
+        println!("eta refresh failed: {}", e.message);
+    }
+    CourierAvailability::touch(auth.id, &conn, &mut redis_conn).await;
     Ok(HttpResponse::Ok().json(""))
 }
 
@@ -58,16 +82,76 @@ pub async fn get_location(
         auth.roles.contains(&"restaurant".to_string()) || 
         auth.roles.contains(&"curator".to_string()),"not admin"); 
     let mut conn = redis_conn.get()?;
+    // restaurants only see positions that can be trusted, staff see the staleness
+    if auth.roles.contains(&"restaurant".to_string()) {
+        let r = get_fresh_coords(&mut conn).await?;
+        return Ok(HttpResponse::Ok().json(r));
+    }
     let r = get_coords(&mut conn).await?;
     Ok(HttpResponse::Ok().json(r))
 }
 
 pub async fn rm_location(
     auth: Auth,
+    conn: web::Data<DbPool>,
     redis_conn: web::Data<RedisDbPool>,
 ) -> Result<HttpResponse> {
     require!(auth.roles.contains(&"courier".to_string()),"not courier"); 
-    let mut conn = redis_conn.get()?;
-    rm_coords(auth.id, &mut conn).await?;
+    let conn = conn.get()?;
+    let mut redis_conn = redis_conn.get()?;
+    rm_coords(auth.id, &mut redis_conn).await?;
+    CourierAvailability::touch(auth.id, &conn, &mut redis_conn).await;
     Ok(HttpResponse::Ok().json(""))
 }
+
+/// the courier's path for a session or an order, to replay disputed deliveries
+pub async fn get_track(
+    auth: Auth,
+    form: web::Json<TrackRequest>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()),"not permitted");
+    let conn = conn.get()?;
+    let points = LocationPoint::get_track(&form, &conn).await?;
+    Ok(HttpResponse::Ok().json(LocationPoint::to_line_string(&form, &points)))
+}
+
+/// default number of couriers a nearest search returns
+const NEAREST_COUNT: usize = 10;
+
+pub async fn get_nearest(
+    auth: Auth,
+    form: web::Json<NearbyRequest>,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()),"not permitted");
+    let conn = conn.get()?;
+    let mut redis_conn = redis_conn.get()?;
+    let point = Coords { lat: form.lat, lng: form.lng };
+    let r = nearest_couriers(&point, form.count.unwrap_or(NEAREST_COUNT), &form.filter,
+        &conn, &mut redis_conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_within(
+    auth: Auth,
+    form: web::Json<NearbyRequest>,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()) ||
+        auth.roles.contains(&"curator".to_string()),"not permitted");
+    let radius = form.radius.ok_or(ApiError {
+        code: 400,
+        message: "radius is expected".to_string(),
+        error_type: ErrorType::InternalError,
+    })?;
+    let conn = conn.get()?;
+    let mut redis_conn = redis_conn.get()?;
+    let point = Coords { lat: form.lat, lng: form.lng };
+    let r = couriers_within(&point, radius, &form.filter, &conn, &mut redis_conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
@@ -0,0 +1,103 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use r2d2_redis::redis;
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{
+    Bigint,
+    Varchar,
+    Nullable,
+};
+use crate::enum_types::*;
+use crate::temp::db::{
+    Coords,
+    get_courier_coords,
+};
+use crate::eta::db::Eta;
+
+#[derive(Serialize,Deserialize,Clone,QueryableByName)]
+pub struct TrackedOrder {
+    #[sql_type="Bigint"]
+    pub order_id: i64,
+    #[sql_type="Orderstatus"]
+    pub status: OrderStatus,
+    #[sql_type="Varchar"]
+    pub restaurant_name: String,
+    #[sql_type="Nullable<Bigint>"]
+    pub courier_id: Option<i64>,
+    #[sql_type="Nullable<Varchar>"]
+    pub courier_name: Option<String>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Tracking {
+    pub status: OrderStatus,
+    pub restaurant_name: String,
+    pub courier_name: Option<String>,
+    pub courier_location: Option<Coords>,
+    pub eta: Option<chrono::NaiveDateTime>,
+}
+
+impl TrackedOrder {
+    pub async fn by_token(
+        token: &str,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::sql_query("SELECT
+                o.id as order_id,
+                o.status,
+                r.name as restaurant_name,
+                c.id as courier_id,
+                c.name as courier_name
+            FROM orders o
+                JOIN restaurants r ON r.id = o.restaurant_id
+                LEFT JOIN sessions s ON s.id = o.session_id
+                LEFT JOIN couriers c ON c.id = s.courier_id
+            WHERE o.tracking_token = $1 AND o.finalize_datetime IS NULL;")
+            .bind::<Varchar,_>(token)
+            .get_result::<Self>(conn)
+            .optional()?
+            .ok_or(ApiError {
+                code: 404,
+                message: "tracking link expired or not exists".to_string(),
+                error_type: ErrorType::InternalError,
+            })?;
+        Ok(r)
+    }
+
+    pub async fn tracking(
+        &self,
+        conn: &PgConnection,
+        conn_redis: &mut redis::Connection,
+    ) -> Result<Tracking> {
+        let courier_visible = match self.status {
+            OrderStatus::CourierFinding | OrderStatus::CourierConfirmation => false,
+            _ => true,
+        };
+        let location = match (&self.status, self.courier_id) {
+            (OrderStatus::Delivering, Some(id)) => get_courier_coords(id, conn_redis)
+                .await?
+                .map(|c| Coords { lat: c.lat, lng: c.lng }),
+            _ => None,
+        };
+        // the page still shows the status when the estimate can't be made
+        let eta = match Eta::get(self.order_id, conn, conn_redis).await {
+            Ok(eta) => eta.delivery_eta,
+            Err(e) => {
+                println!("eta failed: {}", e.message);
+                None
+            },
+        };
+        Ok(Tracking {
+            status: self.status.clone(),
+            restaurant_name: self.restaurant_name.clone(),
+            courier_name: self.courier_name.clone().filter(|_| courier_visible),
+            courier_location: location,
+            eta,
+        })
+    }
+}
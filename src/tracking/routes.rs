@@ -0,0 +1,36 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use r2d2_redis::{RedisConnectionManager, r2d2, redis::{self, Commands}};
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type RedisDbPool = r2d2::Pool<RedisConnectionManager>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use super::db::TrackedOrder;
+
+pub fn tracking_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/tracking")
+        .route("/{token}", web::get().to(get_tracking))
+    );
+}
+
+/// public: the token itself is the only credential
+pub async fn get_tracking(
+    token: web::Path<String>,
+    conn: web::Data<DbPool>,
+    redis_conn: web::Data<RedisDbPool>,
+) -> Result<HttpResponse> {
+    let conn = conn.get()?;
+    let mut redis_conn = redis_conn.get()?;
+    let order = TrackedOrder::by_token(&token, &conn).await?;
+    let r = order.tracking(&conn, &mut redis_conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
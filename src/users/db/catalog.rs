@@ -0,0 +1,343 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use crate::schema::{
+    catalog_items,
+};
+use crate::enum_types::SizeClass;
+use crate::ordering::db::{
+    NewOrderItem,
+    OrderItemRequest,
+};
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct CatalogItem {
+    pub id: i64,
+    pub restaurant_id: i64,
+    pub name: String,
+    pub price: i64,
+    pub weight: Option<i32>,
+    pub size_class: SizeClass,
+    pub is_fragile: bool,
+    pub is_drink: bool,
+    pub is_hot: bool,
+    pub is_available: bool,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable)]
+#[table_name = "catalog_items"]
+pub struct NewCatalogItem {
+    pub name: String,
+    pub price: i64,
+    pub weight: Option<i32>,
+    pub size_class: SizeClass,
+    #[serde(default)]
+    pub is_fragile: bool,
+    #[serde(default)]
+    pub is_drink: bool,
+    #[serde(default)]
+    pub is_hot: bool,
+    #[serde(default = "available")]
+    pub is_available: bool,
+}
+
+fn available() -> bool {
+    true
+}
+
+#[derive(Serialize,Deserialize,Clone,AsChangeset)]
+#[table_name = "catalog_items"]
+pub struct UpdateCatalogItem {
+    pub id: i64,
+    pub name: Option<String>,
+    pub price: Option<i64>,
+    pub weight: Option<i32>,
+    pub size_class: Option<SizeClass>,
+    pub is_fragile: Option<bool>,
+    pub is_drink: Option<bool>,
+    pub is_hot: Option<bool>,
+}
+
+/// file formats accepted by the catalog import
+pub enum CatalogFile {
+    Csv,
+    Xlsx,
+}
+
+/// spreadsheets store numbers as floats, so "1500.0" is accepted
+/// while fractions, NaN and values above `max` are not
+fn whole_number(v: &str, max: i64) -> Option<i64> {
+    if let Ok(n) = v.parse::<i64>() {
+        return Some(n).filter(|n| *n <= max);
+    }
+    let f = v.parse::<f64>().ok()?;
+    if !f.is_finite() || f.fract() != 0.0 || f.abs() > max as f64 {
+        return None;
+    }
+    Some(f as i64)
+}
+
+impl NewCatalogItem {
+    pub fn validate(&self) -> Result<()> {
+        require!(!self.name.is_empty(), "item name is empty");
+        require!(self.price >= 0, "item price can't be negative");
+        require!(self.weight.map(|w| w >= 0).unwrap_or(true), "item weight can't be negative");
+        Ok(())
+    }
+
+    /// row layout: name, price in kopecks, size class, weight in grams,
+    /// fragile, drink, hot, available; the first row is a header
+    fn from_row(row: &[String], line: usize) -> Result<Self> {
+        let err = |column: &str| ApiError {
+            code: 400,
+            message: format!("row {}: bad {}", line, column),
+            error_type: ErrorType::InternalError,
+        };
+        let cell = |i: usize| row.get(i).map(|v| v.trim()).unwrap_or("");
+        // an empty cell takes the default, anything unrecognised is rejected
+        let flag = |i: usize, column: &str, default: bool| match cell(i).to_lowercase().as_str() {
+            "" => Ok(default),
+            "1" | "true" | "yes" | "y" | "да" | "д" => Ok(true),
+            "0" | "false" | "no" | "n" | "нет" | "н" => Ok(false),
+            _ => Err(err(column)),
+        };
+        let size_class = match cell(2).to_lowercase().as_str() {
+            "" | "small" => SizeClass::Small,
+            "medium" => SizeClass::Medium,
+            "large" => SizeClass::Large,
+            "oversized" => SizeClass::Oversized,
+            _ => return Err(err("size class")),
+        };
+        let weight = match cell(3) {
+            "" => None,
+            w => Some(whole_number(w, i32::MAX as i64).ok_or_else(|| err("weight"))? as i32),
+        };
+        let r = Self {
+            name: cell(0).to_string(),
+            price: whole_number(cell(1), i64::MAX).ok_or_else(|| err("price"))?,
+            weight,
+            size_class,
+            is_fragile: flag(4, "fragile", false)?,
+            is_drink: flag(5, "drink", false)?,
+            is_hot: flag(6, "hot", false)?,
+            is_available: flag(7, "available", true)?,
+        };
+        r.validate().map_err(|e| ApiError {
+            code: 400,
+            message: format!("row {}: {}", line, e.message),
+            error_type: ErrorType::InternalError,
+        })?;
+        Ok(r)
+    }
+
+    fn parse(data: &[u8], format: CatalogFile) -> Result<Vec<Self>> {
+        let rows: Vec<Vec<String>> = match format {
+            CatalogFile::Csv => {
+                let mut reader = csv::ReaderBuilder::new()
+                    .has_headers(true)
+                    .flexible(true)
+                    .from_reader(data);
+                let mut rows = vec![];
+                for record in reader.records() {
+                    let record = record.map_err(|e| ApiError {
+                        code: 400,
+                        message: format!("bad csv: {}", e),
+                        error_type: ErrorType::InternalError,
+                    })?;
+                    rows.push(record.iter().map(|v| v.to_string()).collect());
+                }
+                rows
+            },
+            CatalogFile::Xlsx => {
+                use calamine::{Reader, Xlsx};
+                let bad_file = || ApiError {
+                    code: 400,
+                    message: "bad xlsx file".to_string(),
+                    error_type: ErrorType::InternalError,
+                };
+                let mut workbook = Xlsx::new(std::io::Cursor::new(data))
+                    .map_err(|_| bad_file())?;
+                let range = workbook.worksheet_range_at(0)
+                    .ok_or(bad_file())?
+                    .map_err(|_| bad_file())?;
+                range.rows()
+                    .skip(1)
+                    .map(|row| row.iter().map(|v| v.to_string()).collect())
+                    .collect()
+            },
+        };
+        rows.iter()
+            .enumerate()
+            .filter(|(_, row)| row.iter().any(|v| !v.trim().is_empty()))
+            .map(|(i, row)| Self::from_row(row, i + 2))
+            .collect()
+    }
+}
+
+impl CatalogItem {
+    pub async fn new(
+        restaurant_id: i64,
+        data: &NewCatalogItem,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        data.validate()?;
+        let r = diesel::insert_into(catalog_items::table)
+            .values((data, catalog_items::restaurant_id.eq(restaurant_id)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn update(
+        restaurant_id: i64,
+        data: &UpdateCatalogItem,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        require!(data.price.map(|p| p >= 0).unwrap_or(true), "item price can't be negative");
+        let r = diesel::update(catalog_items::table
+                .filter(catalog_items::id.eq(data.id))
+                .filter(catalog_items::restaurant_id.eq(restaurant_id)))
+            .set(data)
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn delete(
+        restaurant_id: i64,
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<()> {
+        diesel::delete(catalog_items::table
+                .filter(catalog_items::id.eq(id))
+                .filter(catalog_items::restaurant_id.eq(restaurant_id)))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub async fn toggle_availability(
+        restaurant_id: i64,
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::update(catalog_items::table
+                .filter(catalog_items::id.eq(id))
+                .filter(catalog_items::restaurant_id.eq(restaurant_id)))
+            .set(catalog_items::is_available.eq(diesel::dsl::not(catalog_items::is_available)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_by_restaurant(
+        restaurant_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = catalog_items::table
+            .filter(catalog_items::restaurant_id.eq(restaurant_id))
+            .order(catalog_items::name)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// inserts new products and overwrites the ones with the same name
+    pub async fn import(
+        restaurant_id: i64,
+        data: &[u8],
+        format: CatalogFile,
+        conn: &PgConnection,
+    ) -> Result<usize> {
+        use diesel::pg::upsert::excluded;
+        let mut items = NewCatalogItem::parse(data, format)?;
+        require!(!items.is_empty(), "file has no items");
+        // a product listed twice is taken from its last row,
+        // one statement can't upsert the same name twice
+        let mut seen = std::collections::HashSet::new();
+        items.reverse();
+        items.retain(|i| seen.insert(i.name.clone()));
+        items.reverse();
+        let r = diesel::insert_into(catalog_items::table)
+            .values(&items.iter()
+                .map(|i| (i, catalog_items::restaurant_id.eq(restaurant_id)))
+                .collect::<Vec<_>>())
+            .on_conflict((catalog_items::restaurant_id, catalog_items::name))
+            .do_update()
+            .set((
+                catalog_items::price.eq(excluded(catalog_items::price)),
+                catalog_items::weight.eq(excluded(catalog_items::weight)),
+                catalog_items::size_class.eq(excluded(catalog_items::size_class)),
+                catalog_items::is_fragile.eq(excluded(catalog_items::is_fragile)),
+                catalog_items::is_drink.eq(excluded(catalog_items::is_drink)),
+                catalog_items::is_hot.eq(excluded(catalog_items::is_hot)),
+                catalog_items::is_available.eq(excluded(catalog_items::is_available)),
+            ))
+            .execute(conn)?;
+        Ok(r)
+    }
+
+    /// turns order items into line items, snapshotting catalog prices
+    pub async fn resolve(
+        restaurant_id: i64,
+        items: &[OrderItemRequest],
+        conn: &PgConnection,
+    ) -> Result<Vec<NewOrderItem>> {
+        let ids = items.iter()
+            .filter_map(|i| i.catalog_item_id)
+            .collect::<Vec<_>>();
+        let catalog = if ids.is_empty() {
+            vec![]
+        } else {
+            catalog_items::table
+                .filter(catalog_items::id.eq_any(&ids))
+                .filter(catalog_items::restaurant_id.eq(restaurant_id))
+                .get_results::<Self>(conn)?
+        };
+        let mut r = vec![];
+        for item in items {
+            let resolved = match item.catalog_item_id {
+                Some(id) => {
+                    let c = catalog.iter()
+                        .find(|c| c.id == id)
+                        .ok_or(ApiError {
+                            code: 404,
+                            message: format!("catalog item {} not found", id),
+                            error_type: ErrorType::InternalError,
+                        })?;
+                    require!(c.is_available, "catalog item is not available");
+                    NewOrderItem {
+                        catalog_item_id: Some(c.id),
+                        name: c.name.clone(),
+                        quantity: item.quantity,
+                        unit_price: c.price,
+                        weight: c.weight,
+                        size_class: c.size_class,
+                        is_fragile: c.is_fragile,
+                        is_drink: c.is_drink,
+                        is_hot: c.is_hot,
+                    }
+                },
+                None => NewOrderItem {
+                    catalog_item_id: None,
+                    name: item.name.clone().unwrap_or_default(),
+                    quantity: item.quantity,
+                    unit_price: item.unit_price.ok_or(ApiError {
+                        code: 400,
+                        message: "item price is missing".to_string(),
+                        error_type: ErrorType::InternalError,
+                    })?,
+                    weight: item.weight,
+                    size_class: item.size_class.unwrap_or(SizeClass::Small),
+                    is_fragile: item.is_fragile,
+                    is_drink: item.is_drink,
+                    is_hot: item.is_hot,
+                },
+            };
+            r.push(resolved);
+        }
+        Ok(r)
+    }
+}
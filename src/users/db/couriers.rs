@@ -30,14 +30,10 @@ pub struct Couriers {
     pub is_warned: bool,
     pub is_deleted: bool,
     pub is_in_order: bool,
-    pub current_rate_ammount: i64,
-    pub current_rate_count: i64,
     pub picture: Option<String>,
-    pub cash: i64,
-    pub term: i64,
-    pub salary: i64,
     pub creation_datetime: chrono::NaiveDateTime,
     pub email: String,
+    pub tier: i16,
 }
 
 #[derive(Serialize,Deserialize,Clone,AsChangeset,Queryable,Identifiable)]
@@ -151,7 +147,7 @@ impl Couriers {
     ) -> Result<Sessions> {
         let r = sessions::table
             .filter(sessions::courier_id.eq(courier_id))
-            .filter(sessions::end_real_time.is_null())
+            .filter(sessions::ended_at.is_null())
             .get_result::<Sessions>(conn)?;
         Ok(r)
     }
@@ -167,45 +163,32 @@ impl Couriers {
         Ok(r)
     }
     
+    /// settles the chosen balances through the ledger
     pub async fn null_money(
         data: &NullMoney,
+        actor_role: &str,
+        actor_id: i64,
         conn: &PgConnection,
     ) -> Result<()> {
-        if data.all {
-        diesel::update(couriers::table)
-            .filter(couriers::id.eq(data.courier_id))
-            .filter(couriers::is_deleted.eq(false))
-            .set((
-                couriers::salary.eq(0),
-                couriers::cash.eq(0),
-                couriers::term.eq(0),
-                ))
-            .execute(conn)?;
+        use crate::ledger::db::LedgerTransaction;
+        use crate::enum_types::LedgerAccount;
+        let accounts = if data.all {
+            vec![
+                LedgerAccount::CourierSalary,
+                LedgerAccount::CourierCash,
+                LedgerAccount::CourierTerminal,
+            ]
         } else if data.salary {
-        diesel::update(couriers::table)
-            .filter(couriers::id.eq(data.courier_id))
-            .filter(couriers::is_deleted.eq(false))
-            .set((
-                couriers::salary.eq(0),
-                ))
-            .execute(conn)?;
+            vec![LedgerAccount::CourierSalary]
         } else if data.card {
-        diesel::update(couriers::table)
-            .filter(couriers::id.eq(data.courier_id))
-            .filter(couriers::is_deleted.eq(false))
-            .set((
-                couriers::term.eq(0),
-                ))
-            .execute(conn)?;
+            vec![LedgerAccount::CourierTerminal]
         } else if data.cash {
-        diesel::update(couriers::table)
-            .filter(couriers::id.eq(data.courier_id))
-            .filter(couriers::is_deleted.eq(false))
-            .set((
-                couriers::cash.eq(0),
-                ))
-            .execute(conn)?;
-        }
+            vec![LedgerAccount::CourierCash]
+        } else {
+            vec![]
+        };
+        Self::from_id(data.courier_id, conn).await?;
+        LedgerTransaction::settle(data.courier_id, &accounts, actor_role, actor_id, conn).await?;
         Ok(())
     }
 
@@ -248,6 +231,19 @@ impl Couriers {
         Ok(())
     }
 
+    pub async fn set_tier(
+        id: i64,
+        tier: i16,
+        conn: &PgConnection,
+    ) -> Result<()> {
+        require!(tier >= 0, "tier can't be negative");
+        diesel::update(couriers::table
+            .filter(couriers::id.eq(id)))
+            .set(couriers::tier.eq(tier))
+            .execute(conn)?;
+        Ok(())
+    }
+
     pub async fn toggle_ban(
         id: i64,
         conn: &PgConnection,
@@ -289,10 +285,6 @@ pub struct CouriersInfo {
     #[sql_type="Varchar"]
     pub courier_phone: String,
     #[sql_type="Bigint"]
-    pub courier_current_rate_count: i64,
-    #[sql_type="Bigint"]
-    pub courier_current_rate_ammount: i64,
-    #[sql_type="Bigint"]
     pub courier_card_balance: i64,
     #[sql_type="Bigint"]
     pub courier_salary: i64,
@@ -328,6 +320,8 @@ pub struct CouriersInfo {
     pub client_phone: String,
     #[sql_type="Transporttype"]
     pub transport: TransportType,
+    #[sql_type="Bigint"]
+    pub order_tip: i64,
 }
 
 impl CouriersInfo {
@@ -354,10 +348,6 @@ pub struct CouriersForAdmin {
     pub patronymic: String,
     #[sql_type="Nullable<Transporttype>"]
     pub transport: Option<TransportType>,
-    #[sql_type="Bigint"]
-    pub current_rate_count: i64,
-    #[sql_type="Bigint"]
-    pub current_rate_amount: i64,
     #[sql_type="Varchar"]
     pub phone: String,
     #[sql_type="Nullable<Varchar>"]
@@ -437,6 +427,8 @@ pub struct CouriersHistory {
     pub destination_lng: f64,
     #[sql_type="Varchar"]
     pub client_comment: String,
+    #[sql_type="Bigint"]
+    pub tip: i64,
 }
 
 impl CouriersHistory {
@@ -444,7 +436,7 @@ impl CouriersHistory {
         id: i64,
         conn: &PgConnection,
     ) -> Result<Vec<Self>> {
-        let r = diesel::sql_query("select * from courier_history where courier_id=$1 and order_status!=ALL('{Success,FailureByCourier,FailureByRestaurant,Delivered}');") 
+        let r = diesel::sql_query("select * from courier_history where courier_id=$1 and order_status!=ALL('{Success,FailureByCourier,FailureByRestaurant,Returned,Delivered}');") 
             .bind::<Bigint,_>(id)
             .get_results(conn)?;
         Ok(r)
@@ -453,7 +445,7 @@ impl CouriersHistory {
         id: i64,
         conn: &PgConnection,
     ) -> Result<Vec<Self>> {
-        let r = diesel::sql_query("select * from courier_history where courier_id=$1 and order_status=ANY('{Success,FailureByCourier,FailureByRestaurant,Delivered}');") 
+        let r = diesel::sql_query("select * from courier_history where courier_id=$1 and order_status=ANY('{Success,FailureByCourier,FailureByRestaurant,Returned,Delivered}');") 
             .bind::<Bigint,_>(id)
             .get_results(conn)?;
         Ok(r)
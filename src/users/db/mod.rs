@@ -2,10 +2,12 @@ pub mod couriers;
 pub mod admins;
 pub mod curators;
 pub mod restaurants;
+pub mod catalog;
 pub use self::restaurants::*;
 pub use self::admins::*;
 pub use self::curators::*;
 pub use self::couriers::*;
+pub use self::catalog::*;
 
 use data_encoding::BASE64;
 use ring::digest::{Context, SHA256};
@@ -31,6 +31,8 @@ pub struct Restaurants {
     pub is_deleted: bool,
     pub creation_datetime: chrono::NaiveDateTime,
     pub email: String,
+    /// matches the restaurant with the shared delivery zones of its city
+    pub city: Option<String>,
 }
 
 #[derive(Serialize,Deserialize,Clone,AsChangeset,Queryable,Identifiable)]
@@ -40,6 +42,7 @@ pub struct UpdateRestaurants {
     pub id: i64,
     pub address: Option<String>,
     pub name: Option<String>,
+    pub city: Option<String>,
 }
 
 #[derive(Serialize,Deserialize,Clone)]
@@ -53,6 +56,8 @@ pub struct NewRestaurant {
     pub email: String,
     pub lng: f64,
     pub lat: f64,
+    #[serde(default)]
+    pub city: Option<String>,
 }
 
 impl Restaurants {
@@ -140,6 +145,7 @@ impl Restaurants {
                 restaurants::working_till.eq(&creds.working_till),
                 restaurants::address.eq(&creds.address),
                 restaurants::email.eq(&creds.email),
+                restaurants::city.eq(&creds.city),
             ))
             .execute(conn)?;
         Ok(())
@@ -242,10 +248,6 @@ pub struct RestaurantsInfo {
     pub courier_patronymic: Option<String>,
     #[sql_type="Nullable<Varchar>"]
     pub courier_phone: Option<String>,
-    #[sql_type="Nullable<Bigint>"]
-    pub courier_rate_amount: Option<i64>,
-    #[sql_type="Nullable<Bigint>"]
-    pub courier_rate_count: Option<i64>,
     #[sql_type="Nullable<Varchar>"]
     pub courier_picture: Option<String>,
     #[sql_type="Bigint"]
@@ -259,7 +261,7 @@ impl RestaurantsInfo {
         restaurant_id: i64,
         conn: &PgConnection,
     ) -> Result<Vec<Self>> {
-        let r = diesel::sql_query("SELECT * FROM restaurant_info WHERE restaurant_id=$1 and status = ANY('{Success,FailureByCourier,FailureByRestaurant}');") 
+        let r = diesel::sql_query("SELECT * FROM restaurant_info WHERE restaurant_id=$1 and status = ANY('{Success,FailureByCourier,FailureByRestaurant,Returned}');") 
             .bind::<Bigint,_>(restaurant_id)
             .get_results(conn)?;
         Ok(r)
@@ -36,6 +36,14 @@ use super::db::{
     RestaurantsInfo,
     NullMoney,
     CouriersHistory,
+    CatalogItem,
+    NewCatalogItem,
+    UpdateCatalogItem,
+    CatalogFile,
+};
+use crate::ratings::db::{
+    CourierRatingBreakdown,
+    WithCourierRating,
 };
 
 #[derive(Deserialize)]
@@ -57,6 +65,14 @@ pub fn users_routes(cfg: &mut web::ServiceConfig) {
             .route("/get_all", web::post().to(get_all_restaurant))
             .route("/order_info", web::post().to(order_info_restaurant))
             .route("/order_history", web::post().to(order_history_restaurant))
+            .service(web::scope("/catalog")
+                .route("/new", web::post().to(new_catalog_item))
+                .route("/update", web::post().to(update_catalog_item))
+                .route("/delete", web::post().to(delete_catalog_item))
+                .route("/toggle_available", web::post().to(toggle_catalog_item))
+                .route("/get", web::post().to(get_catalog))
+                .route("/import", web::post().to(import_catalog))
+            )
         )
         .service(web::scope("/curators")
             .route("/new", web::post().to(create_curator))
@@ -80,6 +96,89 @@ pub fn users_routes(cfg: &mut web::ServiceConfig) {
     );
 }
 
+#[derive(Deserialize)]
+pub struct ImportFormat {
+    format: String,
+}
+
+pub async fn new_catalog_item(
+    auth: Auth,
+    form: web::Json<NewCatalogItem>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CatalogItem::new(auth.id, &form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn update_catalog_item(
+    auth: Auth,
+    form: web::Json<UpdateCatalogItem>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CatalogItem::update(auth.id, &form, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn delete_catalog_item(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CatalogItem::delete(auth.id, form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn toggle_catalog_item(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CatalogItem::toggle_availability(auth.id, form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_catalog(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
+    let conn = conn.get()?;
+    let r = CatalogItem::get_by_restaurant(auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+/// raw file in the body, `?format=csv` or `?format=xlsx`
+pub async fn import_catalog(
+    auth: Auth,
+    query: web::Query<ImportFormat>,
+    body: web::Bytes,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
+    let format = match query.format.as_str() {
+        "csv" => CatalogFile::Csv,
+        "xlsx" => CatalogFile::Xlsx,
+        _ => return Err(ApiError {
+            code: 400,
+            message: "unknown file format".to_string(),
+            error_type: ErrorType::InternalError,
+        }),
+    };
+    let conn = conn.get()?;
+    let r = CatalogItem::import(auth.id, &body, format, &conn).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "imported": r,
+    })))
+}
+
 pub async fn order_history_restaurant(
     auth: Auth,
     conn: web::Data<DbPool>,
@@ -88,6 +187,7 @@ pub async fn order_history_restaurant(
     require!(auth.roles.contains(&"restaurant".to_string()),"not permitted"); 
     let conn = conn.get()?;
     let r = RestaurantsInfo::get_history_by(auth.id, &conn).await?;
+    let r = CourierRatingBreakdown::attach(r, |o| o.courier_id, &conn).await?;
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -100,8 +200,9 @@ pub async fn order_info_restaurant(
     let conn = conn.get()?;
     let mut redis_conn = redis_conn.get()?;
     let r = RestaurantsInfo::get_by(auth.id, &conn).await?;
-    use crate::temp::db::get_coords;
-    let c = get_coords(&mut redis_conn).await?;
+    let r = CourierRatingBreakdown::attach(r, |o| o.courier_id, &conn).await?;
+    use crate::temp::db::get_fresh_coords;
+    let c = get_fresh_coords(&mut redis_conn).await?;
     Ok(HttpResponse::Ok().json(json!({
             "orders": r,
             "coords": c,
@@ -116,7 +217,8 @@ pub async fn null_money_couriers(
     require!(auth.roles.contains(&"curator".to_string()) ||
         auth.roles.contains(&"admin".to_string()),"not permitted"); 
     let conn = conn.get()?;
-    let r = Couriers::null_money(&form, &conn).await?;
+    let role = if auth.roles.contains(&"admin".to_string()) { "admin" } else { "curator" };
+    let r = Couriers::null_money(&form, role, auth.id, &conn).await?;
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -144,6 +246,12 @@ pub async fn get_self_courier(
 ) -> Result<HttpResponse> {
     let conn = conn.get()?;
     let r = CouriersInfo::get_by(auth.id,&conn).await?;
+    let rating = CourierRatingBreakdown::get(auth.id, &conn).await?;
+    let r = WithCourierRating {
+        row: r,
+        courier_rating: rating.rating,
+        courier_ratings_count: rating.ratings_count,
+    };
     Ok(HttpResponse::Ok().json(r))
 }
 
@@ -159,6 +267,7 @@ pub async fn couriers_for_administration(
     let conn = conn.get()?;
     let mut redis_conn = redis_conn.get()?;
     let r = CouriersForAdmin::get(&conn).await?;
+    let r = CourierRatingBreakdown::attach(r, |c| Some(c.id), &conn).await?;
     use crate::temp::db::get_coords;
     let c = get_coords(&mut redis_conn).await?;
     Ok(HttpResponse::Ok().json(json!({
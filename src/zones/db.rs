@@ -0,0 +1,315 @@
+use actix_web_dev::error::{
+    Result,
+    ErrorType,
+    ApiError,
+};
+use serde::{Serialize, Deserialize};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use crate::schema::{
+    delivery_zones,
+    restaurants,
+};
+use crate::temp::db::Coords;
+
+/// rings of one polygon, the first is the outer one, the rest are holes;
+/// points are `[lng, lat]` as in GeoJSON
+type Polygon = Vec<Vec<[f64; 2]>>;
+
+#[derive(Serialize,Deserialize,Clone,Queryable)]
+pub struct DeliveryZone {
+    pub id: i64,
+    /// none for zones shared by every restaurant of the city
+    pub restaurant_id: Option<i64>,
+    pub city: Option<String>,
+    pub name: String,
+    /// GeoJSON `Polygon` or `MultiPolygon` geometry
+    pub geometry: serde_json::Value,
+    pub tariff_multiplier: f64,
+    /// metres by road from the restaurant
+    pub max_distance: Option<i32>,
+    pub is_active: bool,
+    pub admin_id: Option<i64>,
+    pub creation_datetime: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize,Deserialize,Clone,Insertable,AsChangeset)]
+#[table_name="delivery_zones"]
+#[changeset_options(treat_none_as_null="true")]
+pub struct NewDeliveryZone {
+    pub restaurant_id: Option<i64>,
+    pub city: Option<String>,
+    pub name: String,
+    pub geometry: serde_json::Value,
+    pub tariff_multiplier: f64,
+    pub max_distance: Option<i32>,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct UpdateDeliveryZone {
+    pub id: i64,
+    #[serde(flatten)]
+    pub zone: NewDeliveryZone,
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct ImportResult {
+    pub created: usize,
+    pub updated: usize,
+}
+
+fn bad_geometry() -> ApiError {
+    ApiError {
+        code: 400,
+        message: "geometry must be a GeoJSON Polygon or MultiPolygon".to_string(),
+        error_type: ErrorType::InternalError,
+    }
+}
+
+fn polygons(geometry: &serde_json::Value) -> Result<Vec<Polygon>> {
+    let coordinates = geometry.get("coordinates").cloned().ok_or(bad_geometry())?;
+    let r = match geometry.get("type").and_then(|t| t.as_str()) {
+        Some("Polygon") => vec![serde_json::from_value::<Polygon>(coordinates)
+            .map_err(|_| bad_geometry())?],
+        Some("MultiPolygon") => serde_json::from_value::<Vec<Polygon>>(coordinates)
+            .map_err(|_| bad_geometry())?,
+        _ => return Err(bad_geometry()),
+    };
+    require!(!r.is_empty() && r.iter().all(|p| !p.is_empty() && p.iter().all(|ring| ring.len() >= 4)),
+        "polygon ring needs at least 4 points");
+    Ok(r)
+}
+
+/// ray casting, points on the border count as inside for one side only
+fn ring_contains(ring: &[[f64; 2]], point: &Coords) -> bool {
+    let (x, y) = (point.lng, point.lat);
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (xi, yi) = (ring[i][0], ring[i][1]);
+        let (xj, yj) = (ring[j][0], ring[j][1]);
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+fn polygon_contains(polygon: &Polygon, point: &Coords) -> bool {
+    let mut rings = polygon.iter();
+    match rings.next() {
+        Some(outer) => ring_contains(outer, point) &&
+            !rings.any(|hole| ring_contains(hole, point)),
+        None => false,
+    }
+}
+
+impl NewDeliveryZone {
+    fn validate(&self) -> Result<()> {
+        require!(!self.name.trim().is_empty(), "name is empty");
+        require!(self.tariff_multiplier > 0.0, "multiplier must be positive");
+        require!(self.max_distance.map(|d| d > 0).unwrap_or(true), "max distance must be positive");
+        polygons(&self.geometry)?;
+        Ok(())
+    }
+
+    /// properties: `id` (to update an existing zone), `name`, `restaurant_id`,
+    /// `city`, `tariff_multiplier`, `max_distance`
+    fn from_feature(feature: &serde_json::Value) -> Result<(Option<i64>, Self)> {
+        let props = feature.get("properties").cloned().unwrap_or(json!({}));
+        let geometry = feature.get("geometry").cloned().ok_or(bad_geometry())?;
+        let r = Self {
+            restaurant_id: props.get("restaurant_id").and_then(|v| v.as_i64()),
+            city: props.get("city").and_then(|v| v.as_str()).map(|v| v.to_string()),
+            name: props.get("name").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            geometry,
+            tariff_multiplier: props.get("tariff_multiplier").and_then(|v| v.as_f64()).unwrap_or(1.0),
+            max_distance: props.get("max_distance").and_then(|v| v.as_i64()).map(|v| v as i32),
+        };
+        r.validate()?;
+        Ok((props.get("id").and_then(|v| v.as_i64()), r))
+    }
+}
+
+impl DeliveryZone {
+    pub fn contains(&self, point: &Coords) -> bool {
+        polygons(&self.geometry)
+            .map(|p| p.iter().any(|polygon| polygon_contains(polygon, point)))
+            .unwrap_or(false)
+    }
+
+    pub async fn new(
+        data: &NewDeliveryZone,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        data.validate()?;
+        let r = diesel::insert_into(delivery_zones::table)
+            .values((data, delivery_zones::admin_id.eq(admin_id)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn update(
+        data: &UpdateDeliveryZone,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        data.zone.validate()?;
+        let r = diesel::update(delivery_zones::table.filter(delivery_zones::id.eq(data.id)))
+            .set((&data.zone, delivery_zones::admin_id.eq(admin_id)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn toggle(
+        id: i64,
+        conn: &PgConnection,
+    ) -> Result<Self> {
+        let r = diesel::update(delivery_zones::table.filter(delivery_zones::id.eq(id)))
+            .set(delivery_zones::is_active.eq(diesel::dsl::not(delivery_zones::is_active)))
+            .get_result::<Self>(conn)?;
+        Ok(r)
+    }
+
+    pub async fn get_all(
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let r = delivery_zones::table
+            .order(delivery_zones::id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// the restaurant's own zones, or the shared ones of its city if it has none;
+    /// a restaurant without a city only gets its own zones
+    pub async fn get_for_restaurant(
+        restaurant_id: i64,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>> {
+        let own = delivery_zones::table
+            .filter(delivery_zones::restaurant_id.eq(restaurant_id))
+            .filter(delivery_zones::is_active.eq(true))
+            .order(delivery_zones::id)
+            .get_results::<Self>(conn)?;
+        if !own.is_empty() {
+            return Ok(own);
+        }
+        let city = restaurants::table
+            .filter(restaurants::id.eq(restaurant_id))
+            .select(restaurants::city)
+            .get_result::<Option<String>>(conn)?;
+        let city = match city {
+            Some(c) => c,
+            None => return Ok(own),
+        };
+        let r = delivery_zones::table
+            .filter(delivery_zones::restaurant_id.is_null())
+            .filter(delivery_zones::city.eq(city))
+            .filter(delivery_zones::is_active.eq(true))
+            .order(delivery_zones::id)
+            .get_results::<Self>(conn)?;
+        Ok(r)
+    }
+
+    /// the zone the address belongs to; none if no zones are set up,
+    /// an error if the address is outside all of them or too far
+    pub async fn locate(
+        restaurant_id: i64,
+        address: &Coords,
+        distance: f64,
+        conn: &PgConnection,
+    ) -> Result<Option<Self>> {
+        let zones = Self::get_for_restaurant(restaurant_id, conn).await?;
+        if zones.is_empty() {
+            return Ok(None);
+        }
+        let inside = zones.into_iter()
+            .filter(|z| z.contains(address))
+            .collect::<Vec<_>>();
+        require!(!inside.is_empty(), "address is outside the delivery zone");
+        let r = inside.into_iter()
+            .find(|z| z.max_distance.map(|d| distance <= d as f64).unwrap_or(true));
+        require!(r.is_some(), "address is too far for the delivery zone");
+        Ok(r)
+    }
+
+    /// GeoJSON FeatureCollection of the zones
+    pub async fn export(
+        restaurant_id: Option<i64>,
+        conn: &PgConnection,
+    ) -> Result<serde_json::Value> {
+        let mut query = delivery_zones::table
+            .order(delivery_zones::id)
+            .into_boxed();
+        if let Some(id) = restaurant_id {
+            query = query.filter(delivery_zones::restaurant_id.eq(id));
+        }
+        let features = query.get_results::<Self>(conn)?
+            .into_iter()
+            .map(|z| json!({
+                "type": "Feature",
+                "geometry": z.geometry,
+                "properties": {
+                    "id": z.id,
+                    "name": z.name,
+                    "restaurant_id": z.restaurant_id,
+                    "city": z.city,
+                    "tariff_multiplier": z.tariff_multiplier,
+                    "max_distance": z.max_distance,
+                    "is_active": z.is_active,
+                },
+            }))
+            .collect::<Vec<_>>();
+        Ok(json!({
+            "type": "FeatureCollection",
+            "features": features,
+        }))
+    }
+
+    /// features with an `id` overwrite that zone, the rest are created
+    pub async fn import(
+        data: &serde_json::Value,
+        admin_id: i64,
+        conn: &PgConnection,
+    ) -> Result<ImportResult> {
+        require!(data.get("type").and_then(|t| t.as_str()) == Some("FeatureCollection"),
+            "expected a GeoJSON FeatureCollection");
+        let features = data.get("features")
+            .and_then(|f| f.as_array())
+            .cloned()
+            .unwrap_or_default();
+        require!(!features.is_empty(), "no features");
+        let zones = features.iter()
+            .enumerate()
+            .map(|(i, f)| NewDeliveryZone::from_feature(f).map_err(|e| ApiError {
+                code: 400,
+                message: format!("feature {}: {}", i, e.message),
+                error_type: ErrorType::InternalError,
+            }))
+            .collect::<Result<Vec<_>>>()?;
+        let r = conn.transaction::<ImportResult, diesel::result::Error, _>(|| {
+            let mut r = ImportResult { created: 0, updated: 0 };
+            for (id, zone) in zones.iter() {
+                match id {
+                    Some(id) => {
+                        diesel::update(delivery_zones::table.filter(delivery_zones::id.eq(id)))
+                            .set((zone, delivery_zones::admin_id.eq(admin_id)))
+                            .execute(conn)?;
+                        r.updated += 1;
+                    },
+                    None => {
+                        diesel::insert_into(delivery_zones::table)
+                            .values((zone, delivery_zones::admin_id.eq(admin_id)))
+                            .execute(conn)?;
+                        r.created += 1;
+                    },
+                }
+            }
+            Ok(r)
+        })?;
+        Ok(r)
+    }
+}
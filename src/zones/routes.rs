@@ -0,0 +1,163 @@
+use actix_web::{
+    web, http, dev, guard,
+    App, HttpResponse, client::Client,
+    HttpServer, HttpRequest, Responder,
+};
+use serde::Deserialize;
+use diesel::PgConnection;
+use diesel::r2d2::ConnectionManager;
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+use actix_web_dev::error::{
+    Result,
+    ApiError,
+    ErrorType,
+};
+use actix_web_dev::auth::{
+    Auth,
+    AuthSecret,
+};
+use crate::enum_types::TransportType;
+use crate::eta::db::route;
+use crate::temp::db::Coords;
+use crate::users::db::Restaurants;
+use super::db::{
+    DeliveryZone,
+    NewDeliveryZone,
+    UpdateDeliveryZone,
+};
+
+#[derive(Deserialize)]
+pub struct Id {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct ExportFilter {
+    #[serde(default)]
+    restaurant_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct Check {
+    restaurant_id: i64,
+    lat: f64,
+    lng: f64,
+}
+
+pub fn zones_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/zones")
+        .route("/new", web::post().to(new_zone))
+        .route("/update", web::post().to(update_zone))
+        .route("/toggle", web::post().to(toggle_zone))
+        .route("/get_all", web::post().to(get_all))
+        .route("/get_by_restaurant", web::post().to(get_by_restaurant))
+        .route("/check", web::post().to(check))
+        .route("/import", web::post().to(import))
+        .route("/export", web::post().to(export))
+    );
+}
+
+fn is_staff(auth: &Auth) -> bool {
+    auth.roles.contains(&"curator".to_string()) ||
+        auth.roles.contains(&"admin".to_string())
+}
+
+fn is_own_restaurant(auth: &Auth, restaurant_id: i64) -> bool {
+    auth.roles.contains(&"restaurant".to_string()) && auth.id == restaurant_id
+}
+
+pub async fn new_zone(
+    auth: Auth,
+    form: web::Json<NewDeliveryZone>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = DeliveryZone::new(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn update_zone(
+    auth: Auth,
+    form: web::Json<UpdateDeliveryZone>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = DeliveryZone::update(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn toggle_zone(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = DeliveryZone::toggle(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_all(
+    auth: Auth,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = DeliveryZone::get_all(&conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn get_by_restaurant(
+    auth: Auth,
+    form: web::Json<Id>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth) || is_own_restaurant(&auth, form.id),"not permitted"); 
+    let conn = conn.get()?;
+    let r = DeliveryZone::get_for_restaurant(form.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+/// validates an address before the order is created
+pub async fn check(
+    auth: Auth,
+    form: web::Json<Check>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth) || is_own_restaurant(&auth, form.restaurant_id),"not permitted"); 
+    let conn = conn.get()?;
+    let restaurant = Restaurants::from_id(form.restaurant_id, &conn).await?;
+    let from = Coords { lat: restaurant.location_lat, lng: restaurant.location_lng };
+    let to = Coords { lat: form.lat, lng: form.lng };
+    let route = route(&from, &to, &TransportType::Car).await?;
+    let r = DeliveryZone::locate(form.restaurant_id, &to, route.distance, &conn).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "zone": r,
+        "distance": route.distance as i64,
+    })))
+}
+
+pub async fn import(
+    auth: Auth,
+    form: web::Json<serde_json::Value>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(auth.roles.contains(&"admin".to_string()),"not admin"); 
+    let conn = conn.get()?;
+    let r = DeliveryZone::import(&form, auth.id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+pub async fn export(
+    auth: Auth,
+    form: web::Json<ExportFilter>,
+    conn: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    require!(is_staff(&auth),"not permitted"); 
+    let conn = conn.get()?;
+    let r = DeliveryZone::export(form.restaurant_id, &conn).await?;
+    Ok(HttpResponse::Ok().json(r))
+}